@@ -0,0 +1,41 @@
+// Converts PNG to QOI and back with the library, e.g. as a starting point for using it
+// in a program of your own. Run it with
+//
+//   cargo run --example convert -- --to-qoi input.png
+//   cargo run --example convert -- --to-png input.qoi
+//
+// The output goes next to the input, with the other extension.
+
+use std::error::Error;
+use std::path::Path;
+
+use qoi_rust::Colorspace;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [mode, input] = args.as_slice() else {
+        return Err("usage: convert --to-qoi input.png | --to-png input.qoi".into());
+    };
+    let input = Path::new(input);
+
+    match mode.as_str() {
+        "--to-qoi" => {
+            // Let the `image` crate read the PNG, then encode its pixels as QOI. Images
+            // without alpha are encoded from RGB, the rest from RGBA
+            let img = image::open(input)?;
+            let bytes = qoi_rust::encode_dynamic_image(&img, Colorspace::Srgb)?;
+            let output = input.with_extension("qoi");
+            std::fs::write(&output, &bytes)?;
+            println!("{}x{} -> {} ({} bytes)", img.width(), img.height(), output.display(), bytes.len());
+        }
+        "--to-png" => {
+            // Decode the QOI file to RGBA, then hand it to the `image` crate as PNG
+            let image = qoi_rust::decode(&std::fs::read(input)?)?;
+            let output = input.with_extension("png");
+            std::fs::write(&output, image.to_png_bytes()?)?;
+            println!("{} -> {}", image, output.display());
+        }
+        other => return Err(format!("unknown mode {}, expected --to-qoi or --to-png", other).into()),
+    }
+    Ok(())
+}
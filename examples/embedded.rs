@@ -0,0 +1,19 @@
+// Decodes an image compiled into the binary, without touching the filesystem, e.g.
+// for an icon or splash screen shipped inside a program. Run it with
+//
+//   cargo run --example embedded
+
+use qoi_rust::QOI;
+
+// An 8x8 RGBA gradient, 333 bytes
+const TINY: &[u8] = include_bytes!("tiny.qoi");
+
+fn main() -> Result<(), qoi_rust::QoiError> {
+    let file = QOI::try_from(TINY)?;
+    println!("{}x{}, {} channels, {} bytes of pixel data", file.width, file.height, file.channels, file.data().len());
+
+    let image = file.decode()?;
+    let corner = image.get_pixel(image.width - 1, image.height - 1).expect("the corner is inside the image");
+    println!("decoded {} pixels, bottom right is {:?}", image.pixels.len() / 4, corner);
+    Ok(())
+}
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Arbitrary bytes may be rejected, but must never make the decoder panic. The
+// streaming decoder feeds the same op handlers in chunks, so it has to agree
+// with the in-memory one on every input.
+fuzz_target!(|data: &[u8]| {
+    let decoded = qoi_rust::decode(data);
+    let streamed = qoi_rust::decode_from_reader(data);
+
+    match (decoded, streamed) {
+        (Ok(decoded), Ok(streamed)) => {
+            assert_eq!(decoded.pixels.len(), decoded.width as usize * decoded.height as usize * 4);
+            assert_eq!(decoded, streamed);
+        }
+        (Err(_), Err(_)) => {}
+        (decoded, streamed) => panic!("decoders disagree: {:?} vs {:?}", decoded.map(|_| ()), streamed.map(|_| ())),
+    }
+});
@@ -0,0 +1,42 @@
+use alloc::vec::Vec;
+
+use ::base64::alphabet::STANDARD;
+use ::base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use ::base64::Engine;
+
+use crate::{decode, DecodedImage, QoiError};
+
+// Standard base64, with or without the trailing `=` padding, as JSON writers differ
+const ENGINE: GeneralPurpose =
+    GeneralPurpose::new(&STANDARD, GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent));
+
+/// The QOI file encoded as base64 in `text`, e.g. a string from a JSON config.
+/// Whitespace anywhere in the text is skipped, so line-wrapped base64 and a trailing
+/// newline are fine. Text that isn't base64 is `InvalidBase64`.
+pub fn qoi_from_base64(text: &[u8]) -> Result<Vec<u8>, QoiError> {
+    let text: Vec<u8> = text.iter().copied().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    Ok(ENGINE.decode(text)?)
+}
+
+/// Decodes a base64-encoded QOI file, see `qoi_from_base64`.
+pub fn decode_base64(text: &[u8]) -> Result<DecodedImage, QoiError> {
+    decode(&qoi_from_base64(text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_a_base64_encoded_file() {
+        let logo = include_bytes!("../qoi-logo.qoi");
+        let text = ENGINE.encode(logo);
+        assert_eq!(decode_base64(text.as_bytes()).unwrap(), decode(logo).unwrap());
+
+        // Wrapped at 76 columns like MIME, with the padding left out
+        let wrapped: Vec<u8> =
+            text.trim_end_matches('=').as_bytes().chunks(76).flat_map(|line| [line, b"\n"].concat()).collect();
+        assert_eq!(qoi_from_base64(&wrapped).unwrap(), logo);
+        assert!(matches!(decode_base64(b"qoif!"), Err(QoiError::InvalidBase64(_))));
+    }
+}
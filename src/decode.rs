@@ -0,0 +1,910 @@
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{ErrorKind, Read};
+
+use crate::{hash, pixel_count, DecodeOptions, DecodedImage, OpKind, OpStats, Pixel, QoiError, QoiHeader, TracedOp, QOI};
+
+// How many bytes of pixel data `decode_with_progress` decodes between two reports
+const PROGRESS_CHUNK: usize = 64 * 1024;
+
+// How many bytes `decode_from_reader` asks its reader for at a time
+#[cfg(feature = "std")]
+const READ_CHUNK: usize = 64 * 1024;
+
+
+pub fn decode_pixels(file: &QOI) -> Result<Vec<u8>, QoiError> {
+    let mut pixels = Vec::new();
+    decode_into(file, &mut pixels)?;
+    Ok(pixels)
+}
+
+/// Decodes `file` into `out`, reusing its allocation when it is already big enough,
+/// e.g. when decoding many frames of the same size in a loop. `out` is cleared
+/// first, then holds exactly the RGBA pixels. On error `out` is left empty.
+pub fn decode_into(file: &QOI, out: &mut Vec<u8>) -> Result<(), QoiError> {
+    let options = DecodeOptions::default();
+    let mut state = DecodeState::with_buffer(file.width, file.height, file.channels, options, core::mem::take(out))?;
+    state.decode_ops(file.data(), true)?;
+    *out = state.finish()?;
+    Ok(())
+}
+
+// Decoding one image is sequential on purpose. Every op depends on the previous pixel
+// and the index, and a QOI_OP_RGBA doesn't reset either: a later QOI_OP_INDEX can
+// still refer to any color seen before it, so the stream has no restart points.
+// Splitting it would take two passes, a sequential one that follows the previous
+// pixel and the index without writing anything and records them every so often,
+// then decoding the pieces in parallel from those states. Measured on the logo and
+// on images like the bench samples, the first pass alone takes 15% (noise) to 34%
+// (the logo) of a full decode, which caps the speedup at 2.2 to 3.7x on 8 cores
+// before any overhead, and only for images big enough to split. Decoding separate
+// files in parallel, as the command line tool does, needs no extra pass and scales
+// with the number of files, so that is where the parallelism is (see the `batch`
+// bench).
+
+// Decodes the pixel data following `header`, along with how often each op was used
+// and the size of the whole file, up to and including the end marker
+pub(crate) fn decode_image(
+    header: &QoiHeader,
+    data: &[u8],
+    options: DecodeOptions,
+) -> Result<(DecodedImage, OpStats, usize), QoiError> {
+    let mut state = DecodeState::new(header.width, header.height, header.channels, options)?;
+    state.decode_ops(data, true)?;
+    let stats = state.stats;
+    // Without an end marker `finish` fails unless it isn't required, in which case
+    // the image ends where the decode loop stopped
+    let consumed = state.end_marker.map_or(state.offset, |offset| offset + 8);
+    let mut pixels = state.finish()?;
+    if options.premultiply {
+        premultiply_alpha(&mut pixels);
+    }
+    let image = DecodedImage {
+        width: header.width,
+        height: header.height,
+        channels: header.channels,
+        colorspace: header.colorspace,
+        pixels,
+    };
+    Ok((image, stats, consumed))
+}
+
+// Like `decode_image`, but decodes `data` in pieces of `PROGRESS_CHUNK` bytes and
+// reports the pixels decoded so far and the total after each one, and once at the end
+pub(crate) fn decode_image_with_progress<F: FnMut(usize, usize)>(
+    header: &QoiHeader,
+    data: &[u8],
+    mut progress: F,
+) -> Result<DecodedImage, QoiError> {
+    let mut state = DecodeState::new(header.width, header.height, header.channels, DecodeOptions::default())?;
+    let total = state.expected_len / 4;
+    let mut pos = 0;
+    while state.end_marker.is_none() && data.len() - pos > PROGRESS_CHUNK {
+        pos += state.decode_ops(&data[pos..pos + PROGRESS_CHUNK], false)?;
+        progress(state.len / 4, total);
+    }
+    if state.end_marker.is_none() {
+        state.decode_ops(&data[pos..], true)?;
+    }
+    progress(state.len / 4, total);
+
+    Ok(DecodedImage {
+        width: header.width,
+        height: header.height,
+        channels: header.channels,
+        colorspace: header.colorspace,
+        pixels: state.finish()?,
+    })
+}
+
+// Like `decode_image`, but hands every op to `trace` as it is decoded. The data is
+// fed to the decode loop one op at a time, so the ops behave exactly as in a normal
+// decode, only much slower.
+pub(crate) fn decode_image_with_trace<F: FnMut(TracedOp)>(
+    header: &QoiHeader,
+    data: &[u8],
+    mut trace: F,
+) -> Result<DecodedImage, QoiError> {
+    let mut state = DecodeState::new(header.width, header.height, header.channels, DecodeOptions::default())?;
+    let mut i = 0;
+    while i < data.len() && state.end_marker.is_none() {
+        if data[i..].starts_with(&[0, 0, 0, 0, 0, 0, 0, 1]) {
+            state.decode_ops(&data[i..i + 8], true)?;
+            break;
+        }
+        let op = &data[i..(i + op_len(data[i])).min(data.len())];
+        let len = state.len;
+        state.decode_ops(op, true)?;
+        trace(TracedOp {
+            offset: 14 + i,
+            bytes: op,
+            kind: op_kind(op[0]),
+            pixel: Pixel::from_rgba(&state.prev_pixel),
+            pixels: (state.len - len) / 4,
+        });
+        i += op.len();
+    }
+
+    Ok(DecodedImage {
+        width: header.width,
+        height: header.height,
+        channels: header.channels,
+        colorspace: header.colorspace,
+        pixels: state.finish()?,
+    })
+}
+
+// Scales the color channels of RGBA pixels by their alpha, rounded to nearest
+fn premultiply_alpha(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u16;
+        for channel in &mut pixel[..3] {
+            *channel = ((*channel as u16 * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
+/// Decodes only the `width` x `height` rectangle whose top left corner is at `x`, `y`,
+/// e.g. one tile of a map, returning its RGBA pixels row by row. QOI can only be
+/// decoded from the start, but only the rows down to the last one of the rectangle
+/// are decoded and held in memory, so the higher up it is the less work it takes.
+/// Problems in the data past that point go unnoticed.
+pub fn decode_region(file: &QOI, x: u32, y: u32, width: u32, height: u32) -> Result<Vec<u8>, QoiError> {
+    let fits_x = x.checked_add(width).is_some_and(|right| right <= file.width);
+    let fits_y = y.checked_add(height).is_some_and(|bottom| bottom <= file.height);
+    if width == 0 || height == 0 || !fits_x || !fits_y {
+        return Err(QoiError::InvalidRegion { x, y, width, height });
+    }
+    let bottom = y + height;
+
+    // Decoded as if the image ended with the region, stopping once those rows are in
+    let mut state = DecodeState::new(file.width, bottom, file.channels, DecodeOptions::default())?;
+    state.stop_when_full = true;
+    state.decode_ops(file.data(), true)?;
+    if state.len < state.expected_len {
+        if file.data().is_empty() {
+            return Err(QoiError::NoPixelData);
+        }
+        let Some(end_marker) = state.end_marker else {
+            return Err(QoiError::MissingEndMarker { offset: state.offset });
+        };
+        let expected = pixel_count(file.width, file.height)? * 4;
+        return Err(QoiError::LengthMismatch { expected, got: state.len, offset: Some(end_marker) });
+    }
+
+    let row_len = width as usize * 4;
+    let mut region = Vec::with_capacity(row_len * height as usize);
+    for row in y..bottom {
+        let start = (row as usize * file.width as usize + x as usize) * 4;
+        region.extend_from_slice(&state.pixels[start..start + row_len]);
+    }
+    if file.channels == 3 {
+        // `finish` isn't reached, so alpha is fixed up here
+        for pixel in region.chunks_exact_mut(4) {
+            pixel[3] = 255;
+        }
+    }
+    Ok(region)
+}
+
+/// Decodes `file` into packed RGB, 3 bytes per pixel, e.g. for libraries expecting
+/// `Rgb8` data. Meant for RGB sources (`channels == 3`); for RGBA sources the alpha
+/// channel is dropped.
+pub fn decode_rgb(file: &QOI) -> Result<Vec<u8>, QoiError> {
+    let mut pixels = Vec::new();
+    decode_into(file, &mut pixels)?;
+    rgba_to_rgb(&mut pixels);
+    Ok(pixels)
+}
+
+// Packs RGBA pixels into RGB in place by dropping every alpha byte
+pub(crate) fn rgba_to_rgb(pixels: &mut Vec<u8>) {
+    let count = pixels.len() / 4;
+    for n in 0..count {
+        pixels.copy_within(n * 4..n * 4 + 3, n * 3);
+    }
+    pixels.truncate(count * 3);
+}
+
+/// Decodes a QOI image from any reader (a file, a socket, stdin, ...) without
+/// reading the whole file into memory first. The header is parsed up front and
+/// the pixel data is then consumed in chunks.
+#[cfg(feature = "std")]
+pub fn decode_from_reader<R: Read>(mut reader: R) -> Result<DecodedImage, QoiError> {
+    let header = read_header(&mut reader)?;
+    decode_data_from_reader(&header, reader)
+}
+
+// Reads and parses the 14-byte header, leaving `reader` at the start of the pixel data
+#[cfg(feature = "std")]
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> Result<QoiHeader, QoiError> {
+    let mut header = [0u8; 14];
+    reader.read_exact(&mut header).map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof => QoiError::TooSmall,
+        _ => QoiError::Io(err),
+    })?;
+    QoiHeader::parse(&header)
+}
+
+// Decodes the pixel data that follows `header` in `reader`
+#[cfg(feature = "std")]
+pub(crate) fn decode_data_from_reader<R: Read>(header: &QoiHeader, mut reader: R) -> Result<DecodedImage, QoiError> {
+    let mut state = DecodeState::new(header.width, header.height, header.channels, DecodeOptions::default())?;
+    let mut chunk = alloc::vec![0u8; READ_CHUNK];
+    let mut pending = Vec::new(); // Read but not yet decoded, at most one partial op and the marker lookahead
+    while state.end_marker.is_none() {
+        let read = match reader.read(&mut chunk) {
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if read == 0 {
+            state.decode_ops(&pending, true)?;
+            break;
+        }
+
+        pending.extend_from_slice(&chunk[..read]);
+        let consumed = state.decode_ops(&pending, false)?;
+        pending.drain(..consumed);
+    }
+
+    Ok(DecodedImage {
+        width: header.width,
+        height: header.height,
+        channels: header.channels,
+        colorspace: header.colorspace,
+        pixels: state.finish()?,
+    })
+}
+
+
+/// Decodes a QOI file pushed in pieces of any size, down to single bytes, e.g. as it
+/// arrives over the network, handing out pixels as soon as they are decoded.
+///
+/// Ops split between two pieces are kept until the rest arrives. The last few ops
+/// can only be told apart from the end marker once 8 more bytes are in, so the
+/// final pixels come out with the marker.
+pub struct Decoder {
+    options: DecodeOptions,
+    header: Option<QoiHeader>,
+    state: Option<DecodeState>, // Created once the header is in
+    pending: Vec<u8>, // Fed but not decoded yet: the start of the header, or a partial op and the marker lookahead
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    /// A decoder waiting for the first bytes of a file.
+    pub fn new() -> Self {
+        Self::with_options(DecodeOptions::default())
+    }
+
+    /// Like `new`, with the same options as `decode_with_options`.
+    pub fn with_options(options: DecodeOptions) -> Self {
+        Self { options, header: None, state: None, pending: Vec::new() }
+    }
+
+    /// The header, once its 14 bytes have been fed.
+    pub fn header(&self) -> Option<&QoiHeader> {
+        self.header.as_ref()
+    }
+
+    /// Whether the end marker has been seen. Anything fed after it is ignored.
+    pub fn is_done(&self) -> bool {
+        self.state.as_ref().is_some_and(|state| state.end_marker.is_some())
+    }
+
+    /// Decodes as much of `bytes`, and whatever was left over from earlier calls, as
+    /// possible, returning the RGBA pixels that are new since the last call, possibly
+    /// none. They follow on from each other row by row, as in `DecodedImage::pixels`.
+    /// After an error the decoder shouldn't be fed again.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<&[u8], QoiError> {
+        if self.is_done() {
+            return Ok(&[]);
+        }
+        self.pending.extend_from_slice(bytes);
+
+        if self.state.is_none() {
+            if self.pending.len() < 14 {
+                return Ok(&[]);
+            }
+            let header = QoiHeader::parse_with(&self.pending, self.options.lenient_header)?;
+            self.pending.drain(..14);
+            self.state = Some(DecodeState::new(header.width, header.height, header.channels, self.options)?);
+            self.header = Some(header);
+        }
+        let state = self.state.as_mut().expect("the state is created with the header");
+
+        let start = state.len;
+        let consumed = state.decode_ops(&self.pending, false)?;
+        self.pending.drain(..consumed);
+        Ok(state.finish_new_pixels(start, self.options))
+    }
+
+    /// Decodes whatever is left once there is nothing more to feed and returns the
+    /// whole image, with the same checks as `decode_with_options`.
+    pub fn finish(mut self) -> Result<DecodedImage, QoiError> {
+        let (Some(header), Some(mut state)) = (self.header, self.state.take()) else {
+            return Err(QoiError::TooSmall);
+        };
+        if state.end_marker.is_none() {
+            let start = state.len;
+            state.decode_ops(&self.pending, true)?;
+            state.finish_new_pixels(start, self.options);
+        }
+
+        Ok(DecodedImage {
+            width: header.width,
+            height: header.height,
+            channels: header.channels,
+            colorspace: header.colorspace,
+            pixels: state.finish()?,
+        })
+    }
+}
+
+// Everything the decode loop carries from one op to the next, so the pixel data
+// can be fed in pieces
+struct DecodeState {
+    index: [[u8; 4]; 64],
+    prev_pixel: [u8; 4],
+    pixels: Vec<u8>, // Reserved for the whole image up front, `len` bytes long so far
+    len: usize,
+    expected_len: usize,
+    channels: u8,
+    offset: usize, // File offset of the data passed to the next call to `decode_ops`
+    end_marker: Option<usize>, // Offset of the end marker, once found
+    strict: bool,
+    stop_when_full: bool, // Stop at the first pixel past `expected_len` instead of looking for the end marker
+    require_end_marker: bool, // Without it, stop once `expected_len` is reached, marker or not
+    stats: OpStats,
+}
+
+impl DecodeState {
+    fn new(width: u32, height: u32, channels: u8, options: DecodeOptions) -> Result<Self, QoiError> {
+        Self::with_buffer(width, height, channels, options, Vec::new())
+    }
+
+    // Like `new`, but decodes into an existing allocation
+    fn with_buffer(
+        width: u32,
+        height: u32,
+        channels: u8,
+        options: DecodeOptions,
+        mut pixels: Vec<u8>,
+    ) -> Result<Self, QoiError> {
+        let count = pixel_count(width, height)?;
+        // Checked before allocating, since a header alone can claim gigabytes
+        if count > options.max_pixels {
+            return Err(QoiError::ImageTooLarge { width, height });
+        }
+        let expected_len = count * 4; // RGBA requires 4 bytes per pixel
+        pixels.clear();
+        // Allocated once, at the exact size the header gives, and never grown. Only
+        // reserved, the ops fill it, so a header claiming a huge image without the
+        // data for it costs no more than the allocation
+        pixels
+            .try_reserve_exact(expected_len)
+            .map_err(|_| QoiError::ImageTooLarge { width, height })?;
+
+        Ok(Self {
+            index: [[0u8; 4]; 64], // Transparent black, unlike the opaque first prev_pixel, as the spec says
+            prev_pixel: [0u8, 0u8, 0u8, 255u8], // Opaque black as the spec says, never configurable: a first DIFF, LUMA or RUN builds on it
+            pixels,
+            len: 0,
+            expected_len,
+            channels,
+            offset: 14, // The pixel data follows the header, errors point into the whole file
+            end_marker: None,
+            strict: options.strict,
+            stop_when_full: false,
+            require_end_marker: options.require_end_marker,
+            stats: OpStats::default(),
+        })
+    }
+
+    // Decodes ops from `data` and returns how many bytes were consumed. Unless
+    // `last` is set, decoding stops while fewer than 8 bytes are left, since those
+    // could be an op or end marker continuing in the next piece of data.
+    fn decode_ops(&mut self, data: &[u8], last: bool) -> Result<usize, QoiError> {
+        let mut i = 0;
+        while i < data.len() {
+            if !last && data.len() - i < 8 {
+                break;
+            }
+            if i + 8 <= data.len() && data[i..i + 8] == [0, 0, 0, 0, 0, 0, 0, 1] {
+                self.end_marker = Some(self.offset + i);
+                break; // End of file marker
+            }
+            if !self.require_end_marker && self.len == self.expected_len {
+                break;
+            }
+
+            // The one bounds check for the op: the handlers get exactly its bytes
+            let pixel = data[i];
+            let op_len = op_len(pixel);
+            if data.len() - i < op_len {
+                return Err(QoiError::UnexpectedEof { op: pixel, offset: self.offset + i });
+            }
+            let op = &data[i..i + op_len];
+            let prev_pixel = &mut self.prev_pixel;
+            let mut count = 1; // How many times the resulting pixel is written
+
+            match pixel {
+                0b11111110 => { // QOI_OP_RGB
+                    self.stats.rgb += 1;
+                    handle_rgb(op, prev_pixel)
+                }
+                0b11111111 => { // QOI_OP_RGBA
+                    self.stats.rgba += 1;
+                    handle_rgba(op, prev_pixel)
+                }
+                _ if (pixel >> 6) == 0b00 => { // QOI_OP_INDEX
+                    self.stats.index += 1;
+                    handle_index(&self.index, prev_pixel, pixel)
+                }
+                _ if (pixel >> 6) == 0b01 => { // QOI_OP_DIFF
+                    self.stats.diff += 1;
+                    handle_diff(prev_pixel, pixel)
+                }
+                _ if (pixel >> 6) == 0b10 => { // QOI_OP_LUMA
+                    self.stats.luma += 1;
+                    handle_luma(op, prev_pixel)
+                }
+                _ if (pixel >> 6) == 0b11 => { // QOI_OP_RUN
+                    self.stats.run += 1;
+                    count = run_length(pixel);
+                    self.stats.run_pixels += count;
+                }
+                _ => return Err(QoiError::UnknownOp { op: pixel, offset: self.offset + i }),
+            }
+
+            // Corrupt data must not write past the size the header promised. Lenient
+            // decoding drops the extra pixels, like the reference decoder does, but
+            // counts them so callers can tell
+            let remaining = (self.expected_len - self.len) / 4;
+            if count > remaining {
+                if self.strict {
+                    return Err(QoiError::TooManyPixels { offset: self.offset + i });
+                }
+                if self.stop_when_full && remaining == 0 {
+                    break;
+                }
+                self.stats.dropped_pixels += count - remaining;
+                count = remaining;
+            }
+            // Written into the reserved capacity, so nothing is touched before it is
+            // decoded. One pixel at a time on purpose: the compiler already turns this
+            // into wide stores, and filling runs four pixels at a time with a u128 splat
+            // measured no faster (logo 177-179 us scalar vs 185-208 us, solid 485-504 us
+            // vs 518-612 us). The solid image, nearly all 62-pixel runs, decodes 8 MiB in
+            // about 0.5 ms, so it is bound by memory bandwidth, not by this loop
+            let spare = &mut self.pixels.spare_capacity_mut()[..count * 4];
+            for slot in spare.chunks_exact_mut(4) {
+                for (byte, value) in slot.iter_mut().zip(self.prev_pixel) {
+                    byte.write(value);
+                }
+            }
+            self.len += count * 4;
+            // SAFETY: the bytes up to `len` were all written, above or by earlier ops,
+            // and `with_buffer` reserved `expected_len`, which `len` never exceeds
+            unsafe { self.pixels.set_len(self.len) };
+            i += op_len;
+
+            // Update the index after each pixel, QOI_OP_INDEX included, like the reference
+            // decoder. A pixel read from the index hashes back to the slot it came from,
+            // except the [0, 0, 0, 0] of a slot never written, which then lands in slot 0
+            self.index[hash(&self.prev_pixel)] = self.prev_pixel;
+        }
+
+        self.offset += i;
+        Ok(i)
+    }
+
+    // The pixels decoded since `len` was `start`, made final the way `finish` and
+    // `decode_image` would: alpha forced for RGB files and premultiplied if asked for
+    fn finish_new_pixels(&mut self, start: usize, options: DecodeOptions) -> &[u8] {
+        let pixels = &mut self.pixels[start..self.len];
+        if self.channels == 3 {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel[3] = 255;
+            }
+        }
+        if options.premultiply {
+            premultiply_alpha(pixels);
+        }
+        pixels
+    }
+
+    // Checks the decoded data is complete and hands out the pixels
+    fn finish(mut self) -> Result<Vec<u8>, QoiError> {
+        let expected_len = self.expected_len;
+        let mut pixels = core::mem::take(&mut self.pixels);
+
+        // Nothing at all after the header says more than a mismatch against 0 pixels
+        if self.offset == 14 && self.end_marker.is_none() {
+            return Err(QoiError::NoPixelData);
+        }
+        // Running out of data without seeing the marker means the file was truncated or
+        // cut short by a broken encoder, even if the pixel count happens to work out
+        let end = match self.end_marker {
+            Some(end_marker) => end_marker,
+            None if !self.require_end_marker && pixels.len() == expected_len => self.offset,
+            None => return Err(QoiError::MissingEndMarker { offset: self.offset }),
+        };
+
+        if self.strict && pixels.len() < expected_len {
+            return Err(QoiError::TooFewPixels { offset: end });
+        }
+        if pixels.len() != expected_len {
+            return Err(QoiError::LengthMismatch { expected: expected_len, got: pixels.len(), offset: Some(end) });
+        }
+
+        // An RGB file has no meaningful alpha, even if an op (e.g. an index hit on an
+        // untouched slot) carried one. The index itself still has to track the raw
+        // values so hashes match the encoder, so this is only applied to the output.
+        if self.channels == 3 {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel[3] = 255;
+            }
+        }
+        Ok(pixels)
+    }
+}
+
+
+// Length in bytes of the op starting with each tag byte, tag included. A table, as
+// this is looked up for every op
+const OP_LEN: [u8; 256] = {
+    let mut lengths = [1u8; 256]; // QOI_OP_INDEX, QOI_OP_DIFF and QOI_OP_RUN fit in the tag
+    let mut tag = 0b10000000;
+    while tag < 0b11000000 {
+        lengths[tag] = 2; // QOI_OP_LUMA: tag + dr/db byte
+        tag += 1;
+    }
+    lengths[0b11111110] = 4; // QOI_OP_RGB: tag + R, G, B
+    lengths[0b11111111] = 5; // QOI_OP_RGBA: tag + R, G, B, A
+    lengths
+};
+
+fn op_len(tag: u8) -> usize {
+    OP_LEN[tag as usize] as usize
+}
+
+// Which op a tag byte starts
+pub(crate) fn op_kind(tag: u8) -> OpKind {
+    match tag {
+        0b11111110 => OpKind::Rgb,
+        0b11111111 => OpKind::Rgba,
+        _ => match tag >> 6 {
+            0b00 => OpKind::Index,
+            0b01 => OpKind::Diff,
+            0b10 => OpKind::Luma,
+            _ => OpKind::Run,
+        },
+    }
+}
+
+// The op behind each of the first `count` pixels of `data`. Which op it is and how
+// many pixels it makes only depend on the tag, so this just walks the ops, and
+// relies on `data` having been decoded fine before.
+pub(crate) fn op_tags(data: &[u8], count: usize) -> Vec<OpKind> {
+    let mut tags = Vec::with_capacity(count);
+    let mut i = 0;
+    while tags.len() < count {
+        let tag = data[i];
+        let kind = op_kind(tag);
+        let pixels = if kind == OpKind::Run { run_length(tag) } else { 1 };
+        // Like the decoder, a run past the end of the image is cut short
+        tags.extend(core::iter::repeat_n(kind, pixels.min(count - tags.len())));
+        i += op_len(tag);
+    }
+    tags
+}
+
+// The handlers below update `prev_pixel` to the pixel the op stands for. The ones
+// reading more than the tag get the whole op as `op`, exactly `op_len(op[0])` bytes
+// long, so they never need to check bounds themselves.
+
+fn handle_rgb(op: &[u8], prev_pixel: &mut [u8; 4]) {
+    prev_pixel[0] = op[1]; // R
+    prev_pixel[1] = op[2]; // G
+    prev_pixel[2] = op[3]; // B
+    // Alpha carries over from the previous pixel, it is not reset to 255
+}
+
+
+fn handle_rgba(op: &[u8], prev_pixel: &mut [u8; 4]) {
+    prev_pixel[0] = op[1]; // R
+    prev_pixel[1] = op[2]; // G
+    prev_pixel[2] = op[3]; // B
+    prev_pixel[3] = op[4]; // A
+}
+
+fn handle_index(index: &[[u8; 4]; 64], prev_pixel: &mut [u8; 4], pixel: u8) {
+    let idx = (pixel & 0x3F) as usize; // 6 bits, so always within the 64-entry table
+    *prev_pixel = index[idx];
+}
+
+fn handle_diff(prev_pixel: &mut [u8; 4], pixel: u8) {
+    let dr = ((pixel >> 4) & 0x03).wrapping_sub(2);
+    let dg = ((pixel >> 2) & 0x03).wrapping_sub(2);
+    let db = (pixel & 0x03).wrapping_sub(2);
+
+    prev_pixel[0] = prev_pixel[0].wrapping_add(dr); // Apply the diff to the previous red channel
+    prev_pixel[1] = prev_pixel[1].wrapping_add(dg); // Apply the diff to the previous green channel
+    prev_pixel[2] = prev_pixel[2].wrapping_add(db); // Apply the diff to the previous blue channel
+}
+
+// QOI_OP_LUMA is `10gggggg rrrrbbbb`, with the green difference biased by 32 and the
+// red and blue ones stored relative to it, biased by 8:
+//
+//   vg = gggggg - 32             (-32..=31)
+//   vr = vg + rrrr - 8           (-40..=38)
+//   vb = vg + bbbb - 8
+//   r += vr, g += vg, b += vb    all modulo 256, alpha unchanged
+//
+// Adding a negative difference modulo 256 is the same as adding its u8 wrap, so the
+// sums are done in wrapping u8 arithmetic throughout. Checked against the `qoi`
+// crate for every LUMA op on previous pixels near 0 and 255.
+fn handle_luma(op: &[u8], prev_pixel: &mut [u8; 4]) {
+    let vg = (op[0] & 0b00111111).wrapping_sub(32);
+    let vr = vg.wrapping_add(op[1] >> 4).wrapping_sub(8);
+    let vb = vg.wrapping_add(op[1] & 0b1111).wrapping_sub(8);
+
+    prev_pixel[0] = prev_pixel[0].wrapping_add(vr);
+    prev_pixel[1] = prev_pixel[1].wrapping_add(vg);
+    prev_pixel[2] = prev_pixel[2].wrapping_add(vb);
+}
+
+// How many times a QOI_OP_RUN repeats the previous pixel, 1 to 62: 0xFE and 0xFF,
+// which would be 63 and 64, are matched as QOI_OP_RGB and QOI_OP_RGBA first. The op
+// itself is 1 byte; the caller bounds the run to the pixels still missing from the image.
+fn run_length(pixel: u8) -> usize {
+    ((pixel & 0x3F) + 1) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+    use crate::tests::file;
+
+    #[test]
+    fn decode_into_reuses_the_buffer() {
+        let first = QOI::try_from(file(2, 1, 4, &[0xFE, 10, 20, 30, 0xFF, 1, 2, 3, 4])).unwrap();
+        let second = QOI::try_from(file(1, 1, 4, &[0xFE, 50, 60, 70])).unwrap();
+
+        let mut out = Vec::new();
+        decode_into(&first, &mut out).unwrap();
+        assert_eq!(out, [10, 20, 30, 255, 1, 2, 3, 4]);
+        let allocation = out.as_ptr();
+        decode_into(&second, &mut out).unwrap();
+        assert_eq!(out, [50, 60, 70, 255]);
+        assert_eq!(out.as_ptr(), allocation);
+    }
+
+    #[test]
+    fn runs_past_the_last_pixel_are_cut_short() {
+        // A 6-pixel run where only 1 pixel is left
+        let bytes = file(2, 1, 4, &[0xFE, 10, 20, 30, 0xC5]);
+        let image = crate::decode(&bytes).unwrap();
+        assert_eq!(image.pixels, [10, 20, 30, 255, 10, 20, 30, 255]);
+        assert_eq!(image.pixels.capacity(), 8);
+
+        let strict = DecodeOptions::new().strict(true);
+        assert!(matches!(crate::decode_with_options(&bytes, strict), Err(QoiError::TooManyPixels { offset: 18 })));
+    }
+
+    #[test]
+    fn decode_rgb_packs_three_bytes_per_pixel() {
+        let rgb = QOI::try_from(file(3, 2, 3, &[0xFE, 10, 20, 30, 0xC4])).unwrap();
+        let pixels = decode_rgb(&rgb).unwrap();
+        assert_eq!(pixels.len(), 3 * 2 * 3);
+        assert_eq!(pixels, [10, 20, 30].repeat(6));
+
+        // Alpha is dropped from RGBA sources
+        let rgba = QOI::try_from(file(2, 1, 4, &[0xFF, 10, 20, 30, 40, 0xC0])).unwrap();
+        assert_eq!(decode_rgb(&rgba).unwrap(), [10, 20, 30, 10, 20, 30]);
+    }
+
+    #[test]
+    fn ops_ending_right_before_the_marker_decode() {
+        let cases: [(&[u8], [u8; 4]); 3] = [
+            (&[0xFE, 10, 20, 30], [10, 20, 30, 255]),
+            (&[0xFF, 10, 20, 30, 40], [10, 20, 30, 40]),
+            (&[0xA1, 0x88], [1, 1, 1, 255]),
+        ];
+        for (op, pixel) in cases {
+            assert_eq!(crate::decode(file(1, 1, 4, op)).unwrap().pixels, pixel, "{:02X?}", op);
+        }
+    }
+
+    #[test]
+    fn diff_after_an_index_hit_builds_on_the_hit() {
+        let mut state = DecodeState::new(4, 1, 4, DecodeOptions::default()).unwrap();
+        // [10, 20, 30, 255] goes to slot 9, then comes back from it and gets +1 on R, G and B
+        state.decode_ops(&[0xFE, 10, 20, 30, 0xFE, 50, 60, 70, 0x09, 0x7F], true).unwrap();
+        assert_eq!(state.prev_pixel, [11, 21, 31, 255]);
+        assert_eq!(&state.pixels[8..], [10, 20, 30, 255, 11, 21, 31, 255]);
+        assert_eq!(hash(&[11, 21, 31, 255]), 24);
+        assert_eq!(state.index[24], [11, 21, 31, 255]);
+        assert_eq!(state.index[9], [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn strict_mode_points_at_the_problem() {
+        let strict = DecodeOptions::new().strict(true);
+        // One pixel too many: the second op, right after the first one's 4 bytes
+        let over = file(1, 1, 4, &[0xFE, 1, 2, 3, 0xFE, 4, 5, 6]);
+        assert!(matches!(crate::decode_with_options(&over, strict), Err(QoiError::TooManyPixels { offset: 18 })));
+        // One pixel short: the end marker, where the second pixel should have been
+        let under = file(2, 1, 4, &[0xFE, 1, 2, 3]);
+        assert!(matches!(crate::decode_with_options(&under, strict), Err(QoiError::TooFewPixels { offset: 18 })));
+        assert!(matches!(crate::decode(&under), Err(QoiError::LengthMismatch { .. })));
+    }
+
+    #[test]
+    fn rgb_files_decode_opaque_whatever_the_ops_carry() {
+        // An RGBA op with alpha 40, a hit on a slot never written ([0, 0, 0, 0]), a hit
+        // on the RGBA pixel's slot and a run of it
+        let bytes = file(3, 2, 3, &[0xFF, 10, 20, 30, 40, 0x05, 0x0C, 0xC2]);
+        let mut expected = [10, 20, 30, 255, 0, 0, 0, 255].to_vec();
+        expected.extend_from_slice(&[10, 20, 30, 255].repeat(4));
+        assert_eq!(crate::decode(&bytes).unwrap().pixels, expected);
+
+        // The pixels handed out as they are decoded and those of a region go through
+        // other paths
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.feed(&bytes).unwrap(), expected);
+        assert_eq!(decoder.finish().unwrap().pixels, expected);
+        let region = decode_region(&QOI::try_from(bytes).unwrap(), 0, 0, 3, 2).unwrap();
+        assert_eq!(region, expected);
+    }
+
+    #[test]
+    fn premultiply_scales_color_by_alpha() {
+        let bytes = file(2, 1, 4, &[0xFF, 255, 0, 0, 128, 0xFF, 200, 100, 50, 0]);
+        let options = DecodeOptions::new().premultiply(true);
+        let image = crate::decode_with_options(&bytes, options).unwrap();
+        assert_eq!(image.pixels, [128, 0, 0, 128, 0, 0, 0, 0]);
+        assert_eq!(Decoder::with_options(options).feed(&bytes).unwrap(), image.pixels);
+    }
+
+    #[test]
+    fn errors_point_at_where_the_data_went_wrong() {
+        // A 3-pixel run at offset 18 turned into a 1-pixel one by a flipped bit, leaving
+        // the image 2 pixels short when the end marker comes at offset 19
+        let mut bytes = file(4, 1, 4, &[0xFE, 1, 2, 3, 0xC2]);
+        bytes[18] = 0xC0;
+        let err = crate::decode(&bytes).unwrap_err();
+        assert!(matches!(err, QoiError::LengthMismatch { expected: 16, got: 8, offset: Some(19) }), "{:?}", err);
+        let strict = DecodeOptions::new().strict(true);
+        assert!(matches!(crate::decode_with_options(&bytes, strict), Err(QoiError::TooFewPixels { offset: 19 })));
+        let region = decode_region(&QOI::try_from(bytes.clone()).unwrap(), 0, 0, 4, 1);
+        assert!(matches!(region, Err(QoiError::LengthMismatch { offset: Some(19), .. })));
+
+        // The same file cut off right after the run
+        bytes.truncate(19);
+        assert!(matches!(crate::decode(&bytes), Err(QoiError::MissingEndMarker { offset: 19 })));
+        let region = decode_region(&QOI::try_from(bytes).unwrap(), 0, 0, 4, 1);
+        assert!(matches!(region, Err(QoiError::MissingEndMarker { offset: 19 })));
+    }
+
+    #[test]
+    fn truncated_luma_reports_its_offset() {
+        // A second pixel as QOI_OP_LUMA at offset 18, missing its second byte
+        let bytes = [&crate::tests::header(2, 1, 4, 0)[..], &[0xFE, 1, 2, 3, 0xA1]].concat();
+        let err = crate::decode(&bytes).unwrap_err();
+        assert!(matches!(err, QoiError::UnexpectedEof { op: 0xA1, offset: 18 }), "{:?}", err);
+        assert_eq!(err.to_string(), "Unexpected end of file for QOI_OP_LUMA at offset 18");
+    }
+
+    #[test]
+    fn index_starts_out_transparent_black() {
+        // Slots 0 and 53 before anything was written to them, unlike the opaque black
+        // a first run repeats
+        let image = crate::decode(file(3, 1, 4, &[0x00, 0x35, 0xFE, 1, 2, 3])).unwrap();
+        assert_eq!(image.pixels, [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 0]);
+        assert_eq!(crate::decode(file(1, 1, 4, &[0xC0])).unwrap().pixels, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn decoder_matches_decode_whatever_the_piece_size() {
+        let logo = include_bytes!("../qoi-logo.qoi");
+        let expected = crate::decode(logo).unwrap();
+        for size in [1, 7] {
+            let mut decoder = Decoder::new();
+            let mut pixels = Vec::new();
+            for piece in logo.chunks(size) {
+                pixels.extend_from_slice(decoder.feed(piece).unwrap());
+            }
+            assert!(decoder.is_done());
+            assert_eq!(pixels, expected.pixels, "{} byte pieces", size);
+            assert_eq!(decoder.finish().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn decode_region_crops_the_center() {
+        // Pixel n of a 4x4 image has red n
+        let ops: Vec<u8> = (0..16).flat_map(|n| [0xFE, n, 0, 0]).collect();
+        let file = QOI::try_from(file(4, 4, 4, &ops)).unwrap();
+        let center = decode_region(&file, 1, 1, 2, 2).unwrap();
+        let reds: Vec<u8> = center.chunks_exact(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(reds, [5, 6, 9, 10]);
+
+        assert!(matches!(decode_region(&file, 3, 1, 2, 2), Err(QoiError::InvalidRegion { .. })));
+        assert!(matches!(decode_region(&file, 1, 1, 0, 2), Err(QoiError::InvalidRegion { .. })));
+    }
+
+    #[test]
+    fn longest_run_and_the_tags_above_it() {
+        // 0xFD repeats the pixel 62 times, exactly filling the image even when strict
+        let run = file(62, 1, 4, &[0xFD]);
+        assert_eq!(crate::decode(&run).unwrap().pixels, [0, 0, 0, 255].repeat(62));
+        let strict = DecodeOptions::new().strict(true);
+        assert_eq!(crate::decode_with_options(&run, strict).unwrap().pixels.len(), 62 * 4);
+
+        // 0xFE and 0xFF, which would be runs of 63 and 64, are QOI_OP_RGB and QOI_OP_RGBA
+        let (image, stats) = crate::decode_with_stats(&file(2, 1, 4, &[0xFE, 1, 2, 3, 0xFF, 4, 5, 6, 7])).unwrap();
+        assert_eq!(image.pixels, [1, 2, 3, 255, 4, 5, 6, 7]);
+        assert_eq!((stats.rgb, stats.rgba, stats.run), (1, 1, 0));
+    }
+
+    #[test]
+    fn header_alone_is_no_pixel_data() {
+        let header = crate::tests::header(2, 2, 4, 0);
+        assert!(matches!(crate::decode(&header), Err(QoiError::NoPixelData)));
+        let file = QOI::try_from(header).unwrap();
+        assert!(matches!(decode_region(&file, 0, 0, 1, 1), Err(QoiError::NoPixelData)));
+        assert!(matches!(decode_pixels(&file), Err(QoiError::NoPixelData)));
+    }
+
+    #[test]
+    fn luma_by_hand() {
+        // (previous pixel, op, expected pixel), worked out from the spec
+        let cases: [([u8; 4], [u8; 2], [u8; 4]); 4] = [
+            // No change: vg = 32 - 32, and both red and blue at 8 - 8 relative to it
+            ([10, 20, 30, 40], [0xA0, 0x88], [10, 20, 30, 40]),
+            // vg = 37 - 32 = 5, vr = 5 + 0 - 8 = -3, vb = 5 + 15 - 8 = 12
+            ([100, 50, 200, 255], [0xA5, 0x0F], [97, 55, 212, 255]),
+            // Smallest differences below 0: vg = -32, vr = vb = -40
+            ([0, 0, 0, 255], [0x80, 0x00], [216, 224, 216, 255]),
+            // Largest differences past 255: vg = 31, vr = vb = 38, alpha untouched
+            ([250, 250, 250, 7], [0xBF, 0xFF], [32, 25, 32, 7]),
+        ];
+        for (prev, op, expected) in cases {
+            let mut pixel = prev;
+            handle_luma(&op, &mut pixel);
+            assert_eq!(pixel, expected, "{:?} {:02X?}", prev, op);
+        }
+    }
+
+    #[test]
+    fn first_diff_builds_on_opaque_black() {
+        // -1, 0 and +1 from [0, 0, 0, 255], wrapping red around to 255, then +1 on each
+        let image = crate::decode(file(2, 1, 4, &[0x5B, 0x7F])).unwrap();
+        assert_eq!(image.pixels, [255, 0, 1, 255, 0, 1, 2, 255]);
+    }
+
+    #[test]
+    fn dropped_pixels_are_counted() {
+        // 3 pixels in the header, 1 + 10 + 2 in the data
+        let bytes = file(3, 1, 4, &[0xFE, 1, 2, 3, 0xC9, 0xFE, 4, 5, 6, 0xFF, 7, 8, 9, 10]);
+        let (image, stats) = crate::decode_with_stats(&bytes).unwrap();
+        assert_eq!(image.pixels, [1, 2, 3, 255].repeat(3));
+        assert_eq!((stats.run_pixels, stats.dropped_pixels), (10, 10));
+
+        let strict = DecodeOptions::new().strict(true);
+        assert!(matches!(crate::decode_with_options(&bytes, strict), Err(QoiError::TooManyPixels { offset: 18 })));
+        // Nothing is dropped from a file that fits
+        let (_, stats) = crate::decode_with_stats(&file(3, 1, 4, &[0xFE, 1, 2, 3, 0xC1])).unwrap();
+        assert_eq!(stats.dropped_pixels, 0);
+    }
+}
@@ -0,0 +1,45 @@
+use alloc::vec::Vec;
+
+use crate::QoiError;
+
+
+/// The difference between two frames of the same size and layout, byte by byte
+/// (`cur - prev`, wrapping), e.g. consecutive screen captures. Anything that didn't
+/// change comes out as zeros, which the encoder turns into long runs, so encoding
+/// the delta instead of the frame shrinks mostly static sequences a lot.
+/// `apply_frame_delta` turns it back into the frame.
+pub fn frame_delta(prev: &[u8], cur: &[u8]) -> Result<Vec<u8>, QoiError> {
+    if prev.len() != cur.len() {
+        return Err(QoiError::LengthMismatch { expected: prev.len(), got: cur.len(), offset: None });
+    }
+    Ok(prev.iter().zip(cur).map(|(&prev, &cur)| cur.wrapping_sub(prev)).collect())
+}
+
+/// Undoes `frame_delta`: the frame that `delta` was taken of against `prev`.
+pub fn apply_frame_delta(prev: &[u8], delta: &[u8]) -> Result<Vec<u8>, QoiError> {
+    if prev.len() != delta.len() {
+        return Err(QoiError::LengthMismatch { expected: prev.len(), got: delta.len(), offset: None });
+    }
+    Ok(prev.iter().zip(delta).map(|(&prev, &delta)| prev.wrapping_add(delta)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_frames_delta_to_zeros() {
+        let frame: Vec<u8> = (0..64 * 64 * 4).map(|n| (n * 7 % 251) as u8).collect();
+        let delta = frame_delta(&frame, &frame).unwrap();
+        assert!(delta.iter().all(|&byte| byte == 0));
+        // One op for the first pixel and 62-pixel runs for the other 4095
+        let encoded = crate::encode_pixels(64, 64, 4, &delta).unwrap();
+        assert!(encoded.len() < 100, "{} bytes", encoded.len());
+
+        let mut next = frame.clone();
+        next[100] = next[100].wrapping_add(200);
+        let delta = frame_delta(&frame, &next).unwrap();
+        assert_eq!(apply_frame_delta(&frame, &delta).unwrap(), next);
+        assert!(matches!(frame_delta(&frame, &next[4..]), Err(QoiError::LengthMismatch { offset: None, .. })));
+    }
+}
@@ -0,0 +1,421 @@
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::Write;
+
+use crate::{hash, pixel_count, Colorspace, QoiError};
+
+// How many bytes `encode_to_writer` collects before passing them to the writer
+#[cfg(feature = "std")]
+const WRITE_CHUNK: usize = 64 * 1024;
+
+// The index lookup pays off on flat and repetitive images, while on photos and noise
+// it hardly ever hits but still costs a hash and a table write per pixel. So the
+// hits are counted per window of looked-up pixels, and a window with fewer than
+// `MIN_INDEX_HITS` turns the lookup off for the next `INDEX_PAUSE` pixels.
+const INDEX_WINDOW: u32 = 1024;
+const MIN_INDEX_HITS: u32 = 16;
+const INDEX_PAUSE: usize = 16384;
+
+
+/// How `encode_with_options` writes the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// The channel count to write in the header, when it should differ from the
+    /// source pixels. `Some(3)` drops alpha, so no QOI_OP_RGBA is ever written.
+    /// `Some(4)` keeps the alpha channel even if every pixel is opaque, and on RGB
+    /// pixels writes them as RGBA with alpha 255.
+    ///
+    /// `None` writes the source channel count, except that RGBA pixels which are
+    /// all fully opaque are written as RGB, so the header tells decoders there is
+    /// no alpha to care about. The pixel data is the same either way.
+    pub channels: Option<u8>,
+    /// Whether to look pixels up in the index of recently seen colors and write
+    /// QOI_OP_INDEX for hits, on by default. Turning it off skips the lookup and
+    /// the table, trading a somewhat larger file for faster encoding, e.g. for
+    /// real-time capture. The file decodes the same either way.
+    pub use_index: bool,
+    /// The colorspace to write in the header, sRGB by default. Decoders only pass
+    /// it on, the pixels are stored as given either way.
+    pub colorspace: Colorspace,
+    /// Whether to end the file with the 8-byte end marker the spec requires, on by
+    /// default. Some embedded encoders leave it out; turning it off writes the same
+    /// way for them. Such files only decode where the marker is optional, e.g. with
+    /// `DecodeOptions::require_end_marker(false)`.
+    pub write_end_marker: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            channels: None,
+            use_index: true,
+            colorspace: Colorspace::Srgb,
+            write_end_marker: true,
+        }
+    }
+}
+
+/// Encodes `pixels`, `channels` (3 or 4) bytes per pixel row by row, as a QOI file.
+/// Fully opaque RGBA pixels get a 3-channel header, see `EncodeOptions::channels`.
+pub fn encode_pixels(width: u32, height: u32, channels: u8, pixels: &[u8]) -> Result<Vec<u8>, QoiError> {
+    encode_with_options(width, height, channels, pixels, EncodeOptions::default())
+}
+
+/// Like `encode_pixels`, but with control over what is written.
+pub fn encode_with_options(
+    width: u32,
+    height: u32,
+    channels: u8,
+    pixels: &[u8],
+    options: EncodeOptions,
+) -> Result<Vec<u8>, QoiError> {
+    let (total, out_channels) = check_input(width, height, channels, pixels, options)?;
+
+    // Worst case every pixel is a QOI_OP_RGBA (5 bytes), plus header and end marker.
+    // Computed in `usize` and checked, since on 32-bit targets that can be more than
+    // fits in memory even when `pixels` does
+    let mut bytes = Vec::new();
+    total
+        .checked_mul(5)
+        .and_then(|len| len.checked_add(14 + 8))
+        .and_then(|capacity| bytes.try_reserve_exact(capacity).ok())
+        .ok_or(QoiError::ImageTooLarge { width, height })?;
+    write_header(&mut bytes, width, height, out_channels, options.colorspace);
+    // Never full, so everything stays in `bytes`
+    encode_ops(pixels, channels, out_channels, options, &mut bytes, usize::MAX, |_| Ok(()))?;
+    Ok(bytes)
+}
+
+/// Like `encode_pixels`, but writes the file to `writer` as it is encoded, e.g. to a
+/// file or socket, instead of collecting it in memory first. Only a small buffer is
+/// allocated, whatever the image size. The output is the same as `encode_pixels`'s,
+/// and `writer` is flushed at the end.
+#[cfg(feature = "std")]
+pub fn encode_to_writer<W: Write>(
+    width: u32,
+    height: u32,
+    channels: u8,
+    pixels: &[u8],
+    mut writer: W,
+) -> Result<(), QoiError> {
+    let options = EncodeOptions::default();
+    let (_, out_channels) = check_input(width, height, channels, pixels, options)?;
+
+    let mut bytes = Vec::with_capacity(WRITE_CHUNK + 64);
+    write_header(&mut bytes, width, height, out_channels, options.colorspace);
+    encode_ops(pixels, channels, out_channels, options, &mut bytes, WRITE_CHUNK, |bytes| {
+        writer.write_all(bytes)?;
+        bytes.clear();
+        Ok(())
+    })?;
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Validates the input, returning the pixel count and the channel count to write
+fn check_input(
+    width: u32,
+    height: u32,
+    channels: u8,
+    pixels: &[u8],
+    options: EncodeOptions,
+) -> Result<(usize, u8), QoiError> {
+    if channels != 3 && channels != 4 {
+        return Err(QoiError::InvalidChannels(channels));
+    }
+    let total = pixel_count(width, height)?;
+    let expected_len = total * channels as usize;
+    if pixels.len() != expected_len {
+        return Err(QoiError::LengthMismatch { expected: expected_len, got: pixels.len(), offset: None });
+    }
+    let out_channels = match options.channels {
+        Some(out_channels @ (3 | 4)) => out_channels,
+        Some(other) => return Err(QoiError::InvalidChannels(other)),
+        None if channels == 4 && pixels.chunks_exact(4).all(|pixel| pixel[3] == 255) => 3,
+        None => channels,
+    };
+    Ok((total, out_channels))
+}
+
+fn write_header(bytes: &mut Vec<u8>, width: u32, height: u32, channels: u8, colorspace: Colorspace) {
+    bytes.extend_from_slice(b"qoif");
+    bytes.extend_from_slice(&width.to_be_bytes());
+    bytes.extend_from_slice(&height.to_be_bytes());
+    bytes.push(channels);
+    bytes.push(colorspace as u8);
+}
+
+// Appends the ops for `pixels` and the end marker to `bytes`. Whenever `bytes` holds
+// `flush_at` bytes or more, `flush` is called to pass them on and make room.
+fn encode_ops<F: FnMut(&mut Vec<u8>) -> Result<(), QoiError>>(
+    pixels: &[u8],
+    channels: u8,
+    out_channels: u8,
+    options: EncodeOptions,
+    bytes: &mut Vec<u8>,
+    flush_at: usize,
+    mut flush: F,
+) -> Result<(), QoiError> {
+    let total = pixels.len() / channels as usize;
+    let mut index = [[0u8; 4]; 64];
+    let mut index_paused_until = 0; // Pixel number where the lookup resumes
+    let mut index_stale = false;
+    let (mut lookups, mut hits) = (0, 0);
+    let mut prev_pixel = [0u8, 0u8, 0u8, 255u8]; // Same starting pixel as the decoder
+    let mut run = 0u8;
+
+    for (n, chunk) in pixels.chunks_exact(channels as usize).enumerate() {
+        // Checked before each pixel, as runs and index hits skip to the next pixel
+        // early
+        if bytes.len() >= flush_at {
+            flush(bytes)?;
+        }
+
+        // Alpha only counts if the source has it and the file keeps it
+        let alpha = if channels == 4 && out_channels == 4 { chunk[3] } else { 255 };
+        let pixel = [chunk[0], chunk[1], chunk[2], alpha];
+
+        if pixel == prev_pixel {
+            run += 1;
+            // 62 is the longest run: lengths 63 and 64 would be 0xFE and 0xFF, the
+            // QOI_OP_RGB and QOI_OP_RGBA tags, so longer runs take several ops
+            if run == 62 || n + 1 == total {
+                bytes.push(0b11000000 | (run - 1)); // QOI_OP_RUN
+                run = 0;
+            }
+            continue;
+        }
+
+        if run > 0 {
+            bytes.push(0b11000000 | (run - 1)); // QOI_OP_RUN
+            run = 0;
+        }
+
+        if options.use_index && n >= index_paused_until {
+            if index_stale {
+                // The decoder kept filling its table during the pause, so this one is
+                // out of date. Start over with entries no pixel can hit: transparent
+                // black hashes to slot 0 and opaque black to 53, so each entry holds
+                // a color that is never looked up in its slot. Only colors seen from
+                // here on can hit, and the decoder has those in the same slots
+                index = [[0u8; 4]; 64];
+                index[0] = [0, 0, 0, 255];
+                index_stale = false;
+            }
+            if lookups == INDEX_WINDOW {
+                if hits < MIN_INDEX_HITS {
+                    index_paused_until = n + INDEX_PAUSE;
+                    index_stale = true;
+                }
+                (lookups, hits) = (0, 0);
+            }
+            lookups += 1;
+
+            let idx = hash(&pixel);
+            if index[idx] == pixel {
+                hits += 1;
+                bytes.push(idx as u8); // QOI_OP_INDEX
+                prev_pixel = pixel;
+                continue;
+            }
+            index[idx] = pixel;
+        }
+
+        if pixel[3] == prev_pixel[3] {
+            let dr = pixel[0].wrapping_sub(prev_pixel[0]) as i8;
+            let dg = pixel[1].wrapping_sub(prev_pixel[1]) as i8;
+            let db = pixel[2].wrapping_sub(prev_pixel[2]) as i8;
+            let dr_dg = dr.wrapping_sub(dg);
+            let db_dg = db.wrapping_sub(dg);
+
+            if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                // QOI_OP_DIFF
+                bytes.push(0b01000000 | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+            } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                // QOI_OP_LUMA
+                bytes.push(0b10000000 | (dg + 32) as u8);
+                bytes.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+            } else {
+                // QOI_OP_RGB
+                bytes.extend_from_slice(&[0b11111110, pixel[0], pixel[1], pixel[2]]);
+            }
+        } else {
+            // QOI_OP_RGBA
+            bytes.extend_from_slice(&[0b11111111, pixel[0], pixel[1], pixel[2], pixel[3]]);
+        }
+
+        prev_pixel = pixel;
+    }
+
+    if options.write_end_marker {
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]); // End of file marker
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A writer that keeps everything and remembers the largest single write
+    #[cfg(feature = "std")]
+    #[derive(Default)]
+    struct Recorder {
+        bytes: Vec<u8>,
+        largest_write: usize,
+    }
+
+    #[cfg(feature = "std")]
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.largest_write = self.largest_write.max(buf.len());
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Streams the image through `encode_to_writer` and checks it against the in-memory
+    // encoder, byte for byte, and that it came in chunks of about `WRITE_CHUNK`
+    #[cfg(feature = "std")]
+    fn assert_writer_matches(width: u32, height: u32, channels: u8, pixels: &[u8]) {
+        let mut writer = Recorder::default();
+        encode_to_writer(width, height, channels, pixels, &mut writer).unwrap();
+        assert_eq!(writer.bytes, encode_pixels(width, height, channels, pixels).unwrap());
+        // At most one op, plus the end marker, past the chunk size
+        assert!(writer.largest_write <= WRITE_CHUNK + 5 + 8, "{} byte write", writer.largest_write);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn encode_to_writer_matches_encode_pixels() {
+        assert_writer_matches(1, 1, 4, &[1, 2, 3, 4]);
+
+        // Noise, mostly QOI_OP_RGBA, many times `WRITE_CHUNK`
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..512 * 512 * 4)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert_writer_matches(512, 512, 4, &noise);
+        assert_writer_matches(512, 512 * 4 / 3, 3, &noise[..512 * (512 * 4 / 3) * 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn encode_to_writer_flushes_runs_and_index_hits() {
+        // Nothing but QOI_OP_RUN, 1 byte per 62 pixels, and still more than `WRITE_CHUNK`
+        let (width, height) = (2048, 2048);
+        let solid = [0, 0, 0, 255].repeat(width * height);
+        assert!(encode_pixels(width as u32, height as u32, 4, &solid).unwrap().len() > WRITE_CHUNK);
+        assert_writer_matches(width as u32, height as u32, 4, &solid);
+
+        // Two colors taking turns, so all but the first two pixels are QOI_OP_INDEX
+        let alternating = [10, 20, 30, 255, 200, 100, 50, 255].repeat(256 * 512);
+        assert_writer_matches(512, 512, 4, &alternating);
+    }
+
+    // 8x8 squares of black and white
+    fn checkerboard(width: u32, height: u32, channels: usize) -> Vec<u8> {
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let value = if (x / 8 + y / 8) % 2 == 0 { 255 } else { 0 };
+                pixels.extend_from_slice(&[value, value, value, 255][..channels]);
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn checkerboard_round_trips() {
+        let rgba = checkerboard(64, 40, 4);
+        let image = crate::decode(encode_pixels(64, 40, 4, &rgba).unwrap()).unwrap();
+        assert_eq!((image.width, image.height), (64, 40));
+        assert_eq!(image.pixels, rgba);
+
+        // RGB comes back as RGBA with opaque alpha, so the same pixels as above
+        let rgb = checkerboard(64, 40, 3);
+        let image = crate::decode(encode_pixels(64, 40, 3, &rgb).unwrap()).unwrap();
+        assert_eq!(image.channels, 3);
+        assert_eq!(image.pixels, rgba);
+    }
+
+    #[test]
+    fn forced_channels_go_in_the_header() {
+        let translucent: Vec<u8> = (0..64u8).flat_map(|n| [n, 255 - n, n / 2, n * 4]).collect();
+        let rgb = EncodeOptions { channels: Some(3), ..Default::default() };
+        let bytes = encode_with_options(8, 8, 4, &translucent, rgb).unwrap();
+        assert_eq!(bytes[12], 3);
+        let (image, tags) = crate::decode_with_op_tags(&bytes).unwrap();
+        assert!(!tags.contains(&crate::OpKind::Rgba));
+        assert!(image.pixels.chunks_exact(4).all(|pixel| pixel[3] == 255));
+
+        // Opaque RGBA and RGB alike keep 4 channels when asked to
+        let rgba = EncodeOptions { channels: Some(4), ..Default::default() };
+        let opaque = checkerboard(16, 16, 4);
+        assert_eq!(encode_with_options(16, 16, 4, &opaque, rgba).unwrap()[12], 4);
+        let bytes = encode_with_options(16, 16, 3, &checkerboard(16, 16, 3), rgba).unwrap();
+        assert_eq!(bytes[12], 4);
+        assert_eq!(crate::decode(&bytes).unwrap().pixels, opaque);
+    }
+
+    #[test]
+    fn encoding_without_the_index_decodes_the_same() {
+        // Two colors taking turns, all index hits with the index on
+        let alternating = [10, 20, 30, 255, 200, 100, 50, 128].repeat(32);
+        let options = EncodeOptions { use_index: false, ..Default::default() };
+        let bytes = encode_with_options(8, 8, 4, &alternating, options).unwrap();
+        let (image, tags) = crate::decode_with_op_tags(&bytes).unwrap();
+        assert_eq!(image.pixels, alternating);
+        assert!(!tags.contains(&crate::OpKind::Index));
+        assert!(bytes.len() > encode_pixels(8, 8, 4, &alternating).unwrap().len());
+    }
+
+    #[test]
+    fn opaque_rgba_gets_a_three_channel_header() {
+        let gradient: Vec<u8> = (0..256u32).flat_map(|n| [n as u8, (n / 2) as u8, 255 - n as u8, 255]).collect();
+        let bytes = encode_pixels(16, 16, 4, &gradient).unwrap();
+        assert_eq!(bytes[12], 3);
+        let image = crate::decode(&bytes).unwrap();
+        assert_eq!(image.channels, 3);
+        assert_eq!(image.pixels, gradient);
+
+        // A single translucent pixel keeps alpha
+        let mut translucent = gradient;
+        translucent[4 * 100 + 3] = 254;
+        assert_eq!(encode_pixels(16, 16, 4, &translucent).unwrap()[12], 4);
+    }
+
+    #[test]
+    fn long_runs_are_split_at_62_pixels() {
+        // Opaque black, the pixel before the first one, so all 200 pixels are a run
+        let solid = [0, 0, 0, 255].repeat(200);
+        let bytes = encode_pixels(200, 1, 4, &solid).unwrap();
+        let ops = &bytes[14..bytes.len() - 8];
+        assert_eq!(ops, [0xFD, 0xFD, 0xFD, 0xCD]);
+        assert!(ops.iter().all(|&op| op >> 6 == 0b11 && op <= 0xFD));
+        assert_eq!(crate::decode(&bytes).unwrap().pixels, solid);
+    }
+
+    #[test]
+    fn files_without_the_marker_round_trip() {
+        let pixels = checkerboard(16, 16, 4);
+        let options = EncodeOptions { write_end_marker: false, ..Default::default() };
+        let bytes = encode_with_options(16, 16, 4, &pixels, options).unwrap();
+        assert_ne!(bytes[bytes.len() - 8..], [0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bytes.len(), encode_pixels(16, 16, 4, &pixels).unwrap().len() - 8);
+
+        assert!(matches!(crate::decode(&bytes), Err(QoiError::MissingEndMarker { .. })));
+        let lenient = crate::DecodeOptions::new().require_end_marker(false);
+        assert_eq!(crate::decode_with_options(&bytes, lenient).unwrap().pixels, pixels);
+    }
+}
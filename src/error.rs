@@ -0,0 +1,130 @@
+use core::error::Error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+use crate::decode::op_kind;
+
+
+/// Everything that can go wrong while parsing, decoding or encoding a QOI image.
+#[derive(Debug)]
+pub enum QoiError {
+    /// Reading the input failed.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// The input of `decode_base64` isn't valid base64.
+    #[cfg(feature = "base64")]
+    InvalidBase64(base64::DecodeError),
+    /// The input is shorter than the 14-byte header.
+    TooSmall,
+    /// The header is complete but nothing follows it, not even the end marker.
+    NoPixelData,
+    /// The file does not start with `qoif`.
+    BadMagic,
+    /// The file starts with a UTF-8 byte order mark (`EF BB BF`) instead of `qoif`,
+    /// as left by tools that treat every file as text.
+    Utf8Bom,
+    /// The channel count is neither 3 (RGB) nor 4 (RGBA).
+    InvalidChannels(u8),
+    /// The colorspace byte is neither 0 (sRGB) nor 1 (linear).
+    InvalidColorspace(u8),
+    /// The width or height is zero.
+    InvalidDimensions { width: u32, height: u32 },
+    /// The image has more pixels than `DecodeOptions::max_pixels` allows, or is too
+    /// large to be held in memory on this platform.
+    ImageTooLarge { width: u32, height: u32 },
+    /// The data ran out in the middle of the op starting at `offset`. `op` is its tag byte.
+    /// Like all offsets here, `offset` counts bytes from the start of the file.
+    UnexpectedEof { op: u8, offset: usize },
+    /// The op at `offset` produces pixels beyond the image size given in the header.
+    TooManyPixels { offset: usize },
+    /// The end marker at `offset` comes before the image is complete.
+    TooFewPixels { offset: usize },
+    /// The data ends at `offset` without the `00 00 00 00 00 00 00 01` marker.
+    MissingEndMarker { offset: usize },
+    /// The number of decoded (or supplied) pixel bytes doesn't match the dimensions.
+    /// When decoding, `offset` is where the decoded pixels ran out, at the end marker;
+    /// it is `None` for pixels passed in, e.g. to `encode_pixels`.
+    LengthMismatch { expected: usize, got: usize, offset: Option<usize> },
+    /// The byte `op` at `offset` doesn't start any known op.
+    UnknownOp { op: u8, offset: usize },
+    /// The `width` x `height` region at `x`, `y` is empty or doesn't fit in the image.
+    InvalidRegion { x: u32, y: u32, width: u32, height: u32 },
+    /// A tiled container's rows per band, band table or bands don't match the image
+    /// it describes, see `decode_tiled`.
+    InvalidTiles,
+}
+
+impl fmt::Display for QoiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            QoiError::Io(err) => write!(f, "{}", err),
+            #[cfg(feature = "base64")]
+            QoiError::InvalidBase64(err) => write!(f, "Invalid base64: {}", err),
+            QoiError::TooSmall => write!(f, "File too small"),
+            QoiError::NoPixelData => write!(f, "No pixel data after the header"),
+            QoiError::BadMagic => write!(f, "Invalid magic number"),
+            QoiError::Utf8Bom => {
+                write!(f, "File has a UTF-8 BOM; it may have been corrupted by a text tool")
+            }
+            QoiError::InvalidChannels(channels) => write!(f, "Unsupported channel count: {} (expected 3 or 4)", channels),
+            QoiError::InvalidColorspace(colorspace) => {
+                write!(f, "Unsupported colorspace: {} (expected 0 or 1)", colorspace)
+            }
+            QoiError::InvalidDimensions { width, height } => {
+                write!(f, "Invalid image dimensions: {}x{}", width, height)
+            }
+            QoiError::ImageTooLarge { width, height } => write!(f, "Image too large: {}x{}", width, height),
+            QoiError::UnexpectedEof { op, offset } => {
+                write!(f, "Unexpected end of file for {} at offset {}", op_kind(*op), offset)
+            }
+            QoiError::TooManyPixels { offset } => {
+                write!(f, "Pixel data exceeds the image size at offset {}", offset)
+            }
+            QoiError::TooFewPixels { offset } => {
+                write!(f, "Pixel data ends before the image is complete at offset {}", offset)
+            }
+            QoiError::MissingEndMarker { offset } => write!(f, "Missing end of file marker at offset {}", offset),
+            QoiError::LengthMismatch { expected, got, offset: None } => {
+                write!(f, "Pixel data length mismatch: expected {}, got {}", expected, got)
+            }
+            QoiError::LengthMismatch { expected, got, offset: Some(offset) } => {
+                write!(f, "Pixel data length mismatch: expected {}, got {} at offset {}", expected, got, offset)
+            }
+            QoiError::UnknownOp { op, offset } => {
+                write!(f, "Unknown QOI operation: {:08b} at offset {}", op, offset)
+            }
+            QoiError::InvalidRegion { x, y, width, height } => {
+                write!(f, "Invalid region: {}x{} at {},{}", width, height, x, y)
+            }
+            QoiError::InvalidTiles => write!(f, "Invalid tiled container"),
+        }
+    }
+}
+
+impl Error for QoiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            QoiError::Io(err) => Some(err),
+            #[cfg(feature = "base64")]
+            QoiError::InvalidBase64(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for QoiError {
+    fn from(err: io::Error) -> Self {
+        QoiError::Io(err)
+    }
+}
+
+#[cfg(feature = "base64")]
+impl From<base64::DecodeError> for QoiError {
+    fn from(err: base64::DecodeError) -> Self {
+        QoiError::InvalidBase64(err)
+    }
+}
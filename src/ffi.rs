@@ -0,0 +1,95 @@
+// C ABI for using the decoder from C or C++. Build the library with
+//
+//   cargo rustc --lib --release --features ffi --crate-type cdylib      (or staticlib)
+//
+// and declare the functions on the C side as
+//
+//   #include <stddef.h>
+//   #include <stdint.h>
+//
+//   /* Decodes the QOI file in data[0..len]. Returns width * height * 4 bytes of
+//      RGBA pixels, row by row, and stores the size in *out_w and *out_h, or
+//      returns NULL if the data isn't a valid QOI file. The buffer comes from
+//      malloc, so it can be released with qoi_free or free. */
+//   uint8_t *qoi_decode(const uint8_t *data, size_t len, uint32_t *out_w, uint32_t *out_h);
+//
+//   /* Releases a buffer returned by qoi_decode. len is its size in bytes; NULL is ignored. */
+//   void qoi_free(uint8_t *ptr, size_t len);
+
+use core::ffi::c_void;
+use core::ptr;
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+/// Decodes `len` bytes at `data` into a `malloc`ed RGBA buffer, writing the image
+/// size to `out_w` and `out_h`. Returns null on any error.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, and `out_w` and `out_h` must each be
+/// null or point to a writable `u32`.
+#[no_mangle]
+pub unsafe extern "C" fn qoi_decode(data: *const u8, len: usize, out_w: *mut u32, out_h: *mut u32) -> *mut u8 {
+    if data.is_null() {
+        return ptr::null_mut();
+    }
+    let bytes = core::slice::from_raw_parts(data, len);
+    let Ok(image) = crate::decode(bytes) else {
+        return ptr::null_mut();
+    };
+
+    let out = malloc(image.pixels.len()) as *mut u8;
+    if out.is_null() {
+        return ptr::null_mut();
+    }
+    ptr::copy_nonoverlapping(image.pixels.as_ptr(), out, image.pixels.len());
+    if !out_w.is_null() {
+        *out_w = image.width;
+    }
+    if !out_h.is_null() {
+        *out_h = image.height;
+    }
+    out
+}
+
+/// Releases a buffer returned by `qoi_decode`. `len` is only there to keep the ABI
+/// open to allocators that need the size; null is ignored.
+///
+/// # Safety
+///
+/// `ptr` must be null or a buffer from `qoi_decode` that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn qoi_free(ptr: *mut u8, _len: usize) {
+    if !ptr.is_null() {
+        free(ptr as *mut c_void);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::file;
+
+    #[test]
+    fn decode_and_free_round_trip() {
+        let bytes = file(2, 1, 4, &[0xFE, 10, 20, 30, 0xFF, 1, 2, 3, 4]);
+        let (mut width, mut height) = (0, 0);
+        // SAFETY: `bytes` is alive for the call, and the sizes point to local u32s
+        let pixels = unsafe { qoi_decode(bytes.as_ptr(), bytes.len(), &mut width, &mut height) };
+        assert!(!pixels.is_null());
+        assert_eq!((width, height), (2, 1));
+        // SAFETY: `qoi_decode` returned width * height * 4 bytes
+        assert_eq!(unsafe { core::slice::from_raw_parts(pixels, 8) }, [10, 20, 30, 255, 1, 2, 3, 4]);
+        // SAFETY: freed once, and not used afterwards
+        unsafe { qoi_free(pixels, 8) };
+
+        // SAFETY: as above, with the sizes not asked for
+        let broken = unsafe { qoi_decode(bytes.as_ptr(), 10, ptr::null_mut(), ptr::null_mut()) };
+        assert!(broken.is_null());
+        // SAFETY: null is ignored
+        unsafe { qoi_free(broken, 0) };
+    }
+}
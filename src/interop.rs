@@ -0,0 +1,177 @@
+use std::io::{Cursor, Read};
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{ColorType, DynamicImage, ImageBuffer, ImageDecoder, ImageError, ImageFormat, ImageResult, RgbImage, RgbaImage};
+
+use crate::decode::{decode_data_from_reader, read_header, rgba_to_rgb};
+use crate::{encode_with_options, Colorspace, DecodedImage, EncodeOptions, QoiError, QoiHeader};
+
+
+impl DecodedImage {
+    /// Wraps the pixels in an `image::DynamicImage`, as `ImageRgb8` for RGB sources
+    /// and `ImageRgba8` otherwise, to resize, crop or re-encode them with the `image`
+    /// crate.
+    ///
+    /// Panics if `pixels` doesn't hold exactly `width * height` RGBA pixels, which
+    /// can only happen if the fields were changed after decoding.
+    pub fn to_dynamic_image(&self) -> DynamicImage {
+        if self.channels == 3 {
+            let mut pixels = self.pixels.clone();
+            rgba_to_rgb(&mut pixels);
+            let img: RgbImage = ImageBuffer::from_raw(self.width, self.height, pixels)
+                .expect("pixel buffer doesn't match the image dimensions");
+            DynamicImage::ImageRgb8(img)
+        } else {
+            let img: RgbaImage = ImageBuffer::from_raw(self.width, self.height, self.pixels.clone())
+                .expect("pixel buffer doesn't match the image dimensions");
+            DynamicImage::ImageRgba8(img)
+        }
+    }
+
+    /// Encodes the image as PNG in memory, e.g. to send it in an HTTP response
+    /// without going through a file.
+    pub fn to_png_bytes(&self) -> ImageResult<Vec<u8>> {
+        let mut png = Cursor::new(Vec::new());
+        self.to_dynamic_image().write_to(&mut png, ImageFormat::Png)?;
+        Ok(png.into_inner())
+    }
+}
+
+
+/// Encodes anything the `image` crate can load as QOI, the inverse of
+/// `DecodedImage::to_dynamic_image`. Images with an alpha channel are encoded from
+/// RGBA and the rest from RGB. As with `encode_pixels`, RGBA that is fully opaque
+/// gets a 3-channel header.
+///
+/// QOI only stores 8 bits per channel, so 16-bit and floating point images are
+/// reduced to 8 bits first, by `image`'s usual conversion, and the extra precision
+/// is lost.
+pub fn encode_dynamic_image(img: &DynamicImage, colorspace: Colorspace) -> Result<Vec<u8>, QoiError> {
+    let (width, height) = (img.width(), img.height());
+    let options = EncodeOptions { colorspace, ..Default::default() };
+    if img.color().has_alpha() {
+        encode_with_options(width, height, 4, img.to_rgba8().as_raw(), options)
+    } else {
+        encode_with_options(width, height, 3, img.to_rgb8().as_raw(), options)
+    }
+}
+
+/// Decodes QOI through the `image` crate's `ImageDecoder` trait, so QOI files can go
+/// wherever other formats do, e.g. `DynamicImage::from_decoder`.
+pub struct QoiDecoder<R: Read> {
+    header: QoiHeader,
+    reader: R,
+}
+
+impl<R: Read> QoiDecoder<R> {
+    /// Reads the header from `reader`, leaving the pixel data for `read_image`.
+    pub fn new(mut reader: R) -> Result<Self, QoiError> {
+        let header = read_header(&mut reader)?;
+        Ok(Self { header, reader })
+    }
+
+    /// The header read by `new`.
+    pub fn header(&self) -> &QoiHeader {
+        &self.header
+    }
+}
+
+impl<R: Read> ImageDecoder for QoiDecoder<R> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.header.width, self.header.height)
+    }
+
+    fn color_type(&self) -> ColorType {
+        match self.header.channels {
+            3 => ColorType::Rgb8,
+            _ => ColorType::Rgba8,
+        }
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        let image = decode_data_from_reader(&self.header, self.reader).map_err(|err| match err {
+            QoiError::Io(err) => ImageError::IoError(err),
+            err => ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(ImageFormat::Qoi), err)),
+        })?;
+        let mut pixels = image.pixels;
+        if image.channels == 3 {
+            rgba_to_rgb(&mut pixels);
+        }
+        // `image` sizes `buf` from `total_bytes`, which matches the header
+        buf.copy_from_slice(&pixels);
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode;
+    use crate::tests::file;
+
+    #[test]
+    fn to_dynamic_image_keeps_the_layout() {
+        let rgb = decode(file(3, 2, 3, &[0xFE, 10, 20, 30, 0xC4])).unwrap().to_dynamic_image();
+        assert_eq!((rgb.width(), rgb.height()), (3, 2));
+        assert_eq!(rgb.color(), ColorType::Rgb8);
+        assert_eq!(rgb.as_bytes(), [10, 20, 30].repeat(6));
+
+        let rgba = decode(file(2, 3, 4, &[0xFF, 10, 20, 30, 40, 0xC4])).unwrap().to_dynamic_image();
+        assert_eq!((rgba.width(), rgba.height()), (2, 3));
+        assert_eq!(rgba.color(), ColorType::Rgba8);
+        assert_eq!(rgba.as_bytes(), [10, 20, 30, 40].repeat(6));
+    }
+
+    #[test]
+    fn qoi_decoder_works_through_the_trait() {
+        let rgb = file(3, 2, 3, &[0xFE, 10, 20, 30, 0xC4]);
+        let decoder = QoiDecoder::new(Cursor::new(&rgb)).unwrap();
+        assert_eq!(decoder.dimensions(), (3, 2));
+        assert_eq!(decoder.color_type(), ColorType::Rgb8);
+        let img = DynamicImage::from_decoder(decoder).unwrap();
+        assert_eq!(img.as_bytes(), [10, 20, 30].repeat(6));
+
+        let rgba = file(1, 1, 4, &[0xFF, 10, 20, 30, 40]);
+        let decoder = QoiDecoder::new(&rgba[..]).unwrap();
+        assert_eq!(decoder.color_type(), ColorType::Rgba8);
+        assert_eq!(DynamicImage::from_decoder(decoder).unwrap().as_bytes(), [10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn encodes_a_loaded_png() {
+        let pixels = [255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 255, 0, 9, 9, 9, 255].to_vec();
+        let mut png = Cursor::new(Vec::new());
+        let source = DynamicImage::ImageRgba8(RgbaImage::from_raw(2, 2, pixels.clone()).unwrap());
+        source.write_to(&mut png, ImageFormat::Png).unwrap();
+
+        let loaded = image::load_from_memory(png.get_ref()).unwrap();
+        let image = decode(encode_dynamic_image(&loaded, Colorspace::Srgb).unwrap()).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (2, 2, 4));
+        assert_eq!(image.pixels, pixels);
+    }
+
+    #[test]
+    fn to_png_bytes_writes_a_png() {
+        let image = decode(file(3, 2, 4, &[0xFF, 10, 20, 30, 40, 0xC4])).unwrap();
+        let png = image.to_png_bytes().unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8().into_raw(), image.pixels);
+    }
+
+    #[test]
+    fn sixteen_bit_images_are_reduced_to_eight() {
+        // 0x1234 and friends, each reduced to its high byte, 0xFFFF to 255
+        let wide: Vec<u16> = [0x1234, 0xABCD, 0xFFFF, 0x0000, 0x8080, 0x7F7F].to_vec();
+        let img = DynamicImage::ImageRgb16(ImageBuffer::from_raw(2, 1, wide).unwrap());
+        let bytes = encode_dynamic_image(&img, Colorspace::Srgb).unwrap();
+        assert_eq!(&bytes[..4], b"qoif");
+        let image = decode(&bytes).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (2, 1, 3));
+        assert_eq!(image.pixels, img.to_rgba8().into_raw());
+        assert_eq!(image.pixels[..3], [0x12, 0xAB, 0xFF]);
+    }
+}
@@ -0,0 +1,986 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "mmap")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::fs::read;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+#[cfg(feature = "base64")]
+mod base64;
+mod decode;
+mod delta;
+mod encode;
+mod error;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "image")]
+mod interop;
+#[cfg(feature = "png-only")]
+mod png;
+#[cfg(feature = "std")]
+mod ppm;
+#[cfg(feature = "std")]
+mod tga;
+#[cfg(feature = "tiled")]
+mod tiled;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "base64")]
+pub use base64::{decode_base64, qoi_from_base64};
+#[cfg(feature = "std")]
+pub use decode::decode_from_reader;
+pub use decode::{decode_into, decode_pixels, decode_region, decode_rgb, Decoder};
+pub use delta::{apply_frame_delta, frame_delta};
+#[cfg(feature = "std")]
+pub use encode::encode_to_writer;
+pub use encode::{encode_pixels, encode_with_options, EncodeOptions};
+pub use error::QoiError;
+#[cfg(feature = "image")]
+pub use interop::{encode_dynamic_image, QoiDecoder};
+#[cfg(feature = "png-only")]
+pub use png::{write_png, write_png_to};
+#[cfg(feature = "std")]
+pub use ppm::write_ppm;
+#[cfg(feature = "std")]
+pub use tga::write_tga;
+#[cfg(feature = "tiled")]
+pub use tiled::{decode_tiled, encode_tiled};
+#[cfg(feature = "ffi")]
+pub use ffi::{qoi_decode, qoi_free};
+#[cfg(feature = "wasm")]
+pub use wasm::{decode_to_rgba, RgbaImage};
+
+
+/// How the pixel values of an image should be interpreted. Purely informative,
+/// it doesn't change how the pixels are decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Colorspace {
+    /// sRGB color channels with linear alpha
+    Srgb = 0,
+    /// All channels linear
+    Linear = 1,
+}
+
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone)]
+pub struct QOI {
+    pub name: String, // The path it was read from, empty for files parsed from memory
+    pub magic: [char; 4],
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub colorspace: Colorspace,
+    buffer: Vec<u8>, // The whole file as read, header included
+    data_start: usize,
+}
+
+impl QOI {
+    #[cfg(feature = "std")]
+    pub fn new(path: &str) -> Result<Self, QoiError> {
+        let mut file = Self::try_from(read(path)?)?;
+        file.name = path.to_string();
+        Ok(file)
+    }
+
+    /// Decodes the file at `path` straight from a read-only memory map instead of
+    /// reading it into memory first, for files too large to comfortably hold twice.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap(path: &str) -> Result<DecodedImage, QoiError> {
+        let file = File::open(path)?;
+        // SAFETY: the map is only read, and only while this function runs. As with any
+        // mapped file, another process truncating it meanwhile is not guarded against.
+        let map = unsafe { Mmap::map(&file)? };
+        decode(&map)
+    }
+
+    /// The encoded pixel data following the header, end marker included.
+    pub fn data(&self) -> &[u8] {
+        &self.buffer[self.data_start..]
+    }
+
+    /// Decodes the pixel data into a `DecodedImage`.
+    pub fn decode(&self) -> Result<DecodedImage, QoiError> {
+        let mut pixels = Vec::new();
+        decode_into(self, &mut pixels)?;
+        Ok(DecodedImage {
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            colorspace: self.colorspace,
+            pixels,
+        })
+    }
+
+}
+
+/// Parses a QOI file that is already in memory, taking over its buffer.
+impl TryFrom<Vec<u8>> for QOI {
+    type Error = QoiError;
+
+    fn try_from(buffer: Vec<u8>) -> Result<Self, QoiError> {
+        let header = QoiHeader::parse(&buffer)?;
+
+        Ok(Self{
+            name: String::new(),
+            magic: header.magic,
+            width: header.width,
+            height: header.height,
+            channels: header.channels,
+            colorspace: header.colorspace,
+            // Keeping the buffer whole and slicing the pixel data out of it saves
+            // copying it into a buffer of its own
+            buffer,
+            data_start: 14,
+        })
+    }
+}
+
+/// Parses a QOI file that is already in memory, e.g. embedded with `include_bytes!`.
+/// The bytes are copied; use `TryFrom<Vec<u8>>` to hand over an owned buffer instead.
+impl TryFrom<&[u8]> for QOI {
+    type Error = QoiError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, QoiError> {
+        Self::try_from(bytes.to_vec())
+    }
+}
+
+
+// Prints the header fields and only the size of the pixel data, which would
+// otherwise drown everything else
+impl fmt::Debug for QOI {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QOI")
+            .field("name", &self.name)
+            .field("magic", &self.magic)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("channels", &self.channels)
+            .field("colorspace", &self.colorspace)
+            .field("data", &format_args!("{} bytes", self.data().len()))
+            .finish()
+    }
+}
+
+
+/// The 14-byte header at the start of every QOI file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QoiHeader {
+    pub magic: [char; 4],
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub colorspace: Colorspace,
+}
+
+impl QoiHeader {
+    /// Parses and validates the header at the start of `buffer` without touching
+    /// the pixel data, e.g. to get the dimensions of an image without decoding it.
+    /// Only the first 14 bytes are read.
+    pub fn parse(buffer: &[u8]) -> Result<Self, QoiError> {
+        Self::parse_with(buffer, false)
+    }
+
+    /// Like `parse`, but an out-of-range channel count or colorspace, as some buggy
+    /// encoders write, is replaced by 4 (RGBA) or sRGB instead of being an error, to
+    /// recover the pixel data. The magic and the dimensions still have to be valid.
+    pub fn parse_lenient(buffer: &[u8]) -> Result<Self, QoiError> {
+        Self::parse_with(buffer, true)
+    }
+
+    fn parse_with(buffer: &[u8], lenient: bool) -> Result<Self, QoiError> {
+        if buffer.len() < 14 {
+            return Err(QoiError::TooSmall);
+        }
+
+        let magic = [buffer[0] as char, buffer[1] as char, buffer[2] as char, buffer[3] as char];
+
+        if magic != ['q', 'o', 'i', 'f'] {
+            // Worth telling apart, as it points at what broke the file
+            if buffer.starts_with(&[0xEF, 0xBB, 0xBF]) {
+                return Err(QoiError::Utf8Bom);
+            }
+            return Err(QoiError::BadMagic);
+        }
+
+        // Big-endian, whatever the host's byte order. The slices are 4 bytes long, as the
+        // length was checked above
+        let width = u32::from_be_bytes(buffer[4..8].try_into().unwrap());
+        let height = u32::from_be_bytes(buffer[8..12].try_into().unwrap());
+        pixel_count(width, height)?;
+        let channels = match buffer[12] {
+            channels @ (3 | 4) => channels,
+            _ if lenient => 4,
+            other => return Err(QoiError::InvalidChannels(other)),
+        };
+        let colorspace = match buffer[13] {
+            0 => Colorspace::Srgb,
+            1 => Colorspace::Linear,
+            _ if lenient => Colorspace::Srgb,
+            other => return Err(QoiError::InvalidColorspace(other)),
+        };
+
+        Ok(Self {
+            magic,
+            width,
+            height,
+            channels,
+            colorspace,
+        })
+    }
+}
+
+
+/// A fully decoded image along with the header fields needed to interpret it.
+///
+/// `pixels` is always RGBA, 4 bytes per pixel, row-major, whatever the source
+/// `channels` is. For RGB sources (`channels == 3`) every alpha byte is 255.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub colorspace: Colorspace,
+    pub pixels: Vec<u8>,
+}
+
+impl DecodedImage {
+    /// Iterates over the pixels row by row, top to bottom.
+    pub fn pixels_iter(&self) -> impl Iterator<Item = Pixel> + '_ {
+        self.pixels.chunks_exact(4).map(Pixel::from_rgba)
+    }
+
+    /// The pixel at column `x` of row `y`, or `None` if that's outside the image.
+    pub fn get_pixel(&self, x: u32, y: u32) -> Option<Pixel> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let start = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels.get(start..start + 4).map(Pixel::from_rgba)
+    }
+
+    /// A smaller copy for previews, keeping every `factor`th pixel of every `factor`th
+    /// row (nearest neighbor). The size is rounded up, so nothing shrinks to zero:
+    /// a 5x5 image downscaled by 2 is 3x3. QOI ops depend on all the pixels before
+    /// them, so this works on the fully decoded image rather than while decoding.
+    ///
+    /// Panics if `factor` is 0.
+    pub fn downscale(&self, factor: u32) -> DecodedImage {
+        assert!(factor > 0, "downscale factor must be at least 1");
+        let width = self.width.div_ceil(factor);
+        let height = self.height.div_ceil(factor);
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in (0..self.height).step_by(factor as usize) {
+            let row = y as usize * self.width as usize;
+            for x in (0..self.width).step_by(factor as usize) {
+                let start = (row + x as usize) * 4;
+                pixels.extend_from_slice(&self.pixels[start..start + 4]);
+            }
+        }
+
+        DecodedImage {
+            width,
+            height,
+            channels: self.channels,
+            colorspace: self.colorspace,
+            pixels,
+        }
+    }
+
+    /// The same image with `channels` set to 3 or 4, whatever the header said, for
+    /// consumers that need one fixed layout: `to_raw`, `to_dynamic_image` and the
+    /// files written from them follow it. Forcing 3 drops alpha, setting it to 255
+    /// in `pixels` too; forcing 4 on an RGB image gives RGBA with alpha 255. Other
+    /// counts are `InvalidChannels`.
+    pub fn with_channels(mut self, channels: u8) -> Result<Self, QoiError> {
+        match channels {
+            3 => {
+                for pixel in self.pixels.chunks_exact_mut(4) {
+                    pixel[3] = 255;
+                }
+            }
+            4 => {}
+            other => return Err(QoiError::InvalidChannels(other)),
+        }
+        self.channels = channels;
+        Ok(self)
+    }
+
+    /// Whether the pixels look like they were premultiplied by alpha before encoding,
+    /// which QOI doesn't expect, e.g. to catch a broken asset pipeline. A diagnostic
+    /// only, it changes nothing.
+    ///
+    /// In premultiplied data no color channel can exceed its pixel's alpha, while
+    /// straight alpha allows anything. So this is true when no translucent pixel has a
+    /// channel above its alpha and at least one has some color at all; translucent
+    /// black looks the same either way. Opaque images are never flagged. A straight
+    /// image whose translucent pixels all happen to be dark can be flagged too, the
+    /// more translucent pixels an image has, the more telling the answer.
+    pub fn looks_premultiplied(&self) -> bool {
+        let mut colored = false;
+        for pixel in self.pixels.chunks_exact(4).filter(|pixel| pixel[3] < 255) {
+            let max = pixel[0].max(pixel[1]).max(pixel[2]);
+            if max > pixel[3] {
+                return false;
+            }
+            colored |= max > 0;
+        }
+        colored
+    }
+
+    /// The pixels in the layout of the source image: RGB for `channels == 3`,
+    /// RGBA otherwise. Row-major with no padding or header, so exactly
+    /// `width * height * channels` bytes.
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut raw = self.pixels.clone();
+        if self.channels == 3 {
+            decode::rgba_to_rgb(&mut raw);
+        }
+        raw
+    }
+
+    /// The pixels widened to 16 bits per channel, RGBA like `pixels`, for pipelines
+    /// that expect 16-bit buffers. Each value is repeated in both bytes (`x * 257`),
+    /// so 0 stays 0 and 255 becomes 65535, the full range, unlike a plain shift.
+    pub fn to_rgba16(&self) -> Vec<u16> {
+        self.pixels.iter().map(|&channel| channel as u16 * 257).collect()
+    }
+
+    /// One luma byte per pixel, row by row, e.g. as input for a model that wants a
+    /// single channel. Uses the Rec.601 weights (`0.299 R + 0.587 G + 0.114 B`, rounded),
+    /// like Pillow's and OpenCV's grayscale conversions, applied to the stored values
+    /// without linearizing. White stays 255, black stays 0 and alpha is ignored.
+    pub fn to_grayscale(&self) -> Vec<u8> {
+        self.pixels
+            .chunks_exact(4)
+            .map(|p| ((299 * p[0] as u32 + 587 * p[1] as u32 + 114 * p[2] as u32 + 500) / 1000) as u8)
+            .collect()
+    }
+
+    /// The pixels as linear-light RGBA floats in `0.0..=1.0`, e.g. for compositing.
+    /// For `Colorspace::Srgb` images R, G and B go through the sRGB transfer function
+    /// while alpha, linear already, is only scaled; `Colorspace::Linear` images are
+    /// only scaled.
+    #[cfg(feature = "std")]
+    pub fn to_linear_f32(&self) -> Vec<f32> {
+        let scaled: [f32; 256] = core::array::from_fn(|n| n as f32 / 255.0);
+        let color = match self.colorspace {
+            Colorspace::Srgb => scaled.map(|c| {
+                if c <= 0.04045 {
+                    c / 12.92
+                } else {
+                    ((c + 0.055) / 1.055).powf(2.4)
+                }
+            }),
+            Colorspace::Linear => scaled,
+        };
+
+        let mut linear = Vec::with_capacity(self.pixels.len());
+        for pixel in self.pixels.chunks_exact(4) {
+            linear.extend_from_slice(&[
+                color[pixel[0] as usize],
+                color[pixel[1] as usize],
+                color[pixel[2] as usize],
+                scaled[pixel[3] as usize],
+            ]);
+        }
+        linear
+    }
+
+    /// Writes `to_raw` to `path` as is, e.g. for uploading straight into a texture.
+    #[cfg(feature = "std")]
+    pub fn save_raw(&self, path: impl AsRef<Path>) -> Result<(), QoiError> {
+        std::fs::write(path, self.to_raw())?;
+        Ok(())
+    }
+}
+
+// A one-line summary for logs, e.g. `DecodedImage 1920x1080 RGBA sRGB (8.3 MB)`. The
+// size is that of `pixels` in decimal units, the pixels themselves are left out.
+impl fmt::Display for DecodedImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layout = if self.channels == 3 { "RGB" } else { "RGBA" };
+        let colorspace = match self.colorspace {
+            Colorspace::Srgb => "sRGB",
+            Colorspace::Linear => "linear",
+        };
+        write!(f, "DecodedImage {}x{} {} {} (", self.width, self.height, layout, colorspace)?;
+        let size = self.pixels.len();
+        match size {
+            0..=999 => write!(f, "{} B)", size),
+            1_000..=999_999 => write!(f, "{:.1} KB)", size as f64 / 1e3),
+            1_000_000..=999_999_999 => write!(f, "{:.1} MB)", size as f64 / 1e6),
+            _ => write!(f, "{:.1} GB)", size as f64 / 1e9),
+        }
+    }
+}
+
+
+/// A single straight-alpha RGBA pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pixel {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Pixel {
+    fn from_rgba(rgba: &[u8]) -> Self {
+        Self { r: rgba[0], g: rgba[1], b: rgba[2], a: rgba[3] }
+    }
+}
+
+
+/// The default for `DecodeOptions::max_pixels`, the same limit as the reference
+/// decoder: 400 million pixels, 1.6 GB of RGBA.
+pub const DEFAULT_MAX_PIXELS: usize = 400_000_000;
+
+/// How `decode_with_options` treats the data and what it hands out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Off by default: pixels beyond the image size are dropped, as reference
+    /// decoders do. When set, they are a `TooManyPixels` error instead, and data
+    /// that ends before the image is complete is `TooFewPixels` rather than
+    /// `LengthMismatch`, both with the offset where they diverged from the header.
+    pub strict: bool,
+    /// Multiply R, G and B by alpha, as many GPU pipelines expect. Off by default,
+    /// leaving straight alpha like the source has.
+    pub premultiply: bool,
+    /// Images with more pixels than this are rejected as `ImageTooLarge` right after
+    /// the header is read, before anything is allocated, so a tiny file claiming
+    /// huge dimensions can't exhaust memory, e.g. on a server decoding uploads.
+    /// `DEFAULT_MAX_PIXELS` by default, which every decode without options uses
+    /// too, and `usize::MAX` for no limit.
+    pub max_pixels: usize,
+    /// Read the header with `QoiHeader::parse_lenient`, so a bad channel count or
+    /// colorspace falls back to RGBA and sRGB. Off by default.
+    pub lenient_header: bool,
+    /// On by default: data that ends without the end marker is `MissingEndMarker`.
+    /// Turning it off accepts files from encoders that leave the marker out, see
+    /// `EncodeOptions::write_end_marker`. Decoding then stops as soon as the header's
+    /// pixel count is reached and ignores anything after it, as there is no way to
+    /// tell extra pixels from trailing data; a marker right there is still skipped.
+    pub require_end_marker: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            premultiply: false,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            lenient_header: false,
+            require_end_marker: true,
+        }
+    }
+}
+
+impl DecodeOptions {
+    /// The defaults, to be adjusted with the methods below, e.g.
+    /// `DecodeOptions::new().strict(true).build()`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `strict`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets `premultiply`.
+    pub fn premultiply(mut self, premultiply: bool) -> Self {
+        self.premultiply = premultiply;
+        self
+    }
+
+    /// Sets `max_pixels`.
+    pub fn max_pixels(mut self, max_pixels: usize) -> Self {
+        self.max_pixels = max_pixels;
+        self
+    }
+
+    /// Sets `lenient_header`.
+    pub fn lenient_header(mut self, lenient_header: bool) -> Self {
+        self.lenient_header = lenient_header;
+        self
+    }
+
+    /// Sets `require_end_marker`.
+    pub fn require_end_marker(mut self, require_end_marker: bool) -> Self {
+        self.require_end_marker = require_end_marker;
+        self
+    }
+
+    /// Ends the chain. The options are ready to use without it too, this only
+    /// makes the end of a long chain easier to spot.
+    pub fn build(self) -> Self {
+        self
+    }
+}
+
+/// How many of each op a QOI stream is made of, e.g. to see why a file is as
+/// large or small as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpStats {
+    pub rgb: usize,
+    pub rgba: usize,
+    pub index: usize,
+    pub diff: usize,
+    pub luma: usize,
+    pub run: usize,
+    /// Pixels produced by all `QOI_OP_RUN`s together.
+    pub run_pixels: usize,
+    /// Pixels the ops produced past the size in the header, which were dropped.
+    /// Anything but 0 means the file is corrupt or its header is wrong. Strict
+    /// decoding rejects such files instead.
+    pub dropped_pixels: usize,
+}
+
+/// The op a pixel came from, as reported by `decode_with_op_tags`. Displays as the
+/// spec's name, e.g. `QOI_OP_RGB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpKind {
+    Rgb,
+    Rgba,
+    Index,
+    Diff,
+    Luma,
+    Run,
+}
+
+impl fmt::Display for OpKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OpKind::Rgb => "QOI_OP_RGB",
+            OpKind::Rgba => "QOI_OP_RGBA",
+            OpKind::Index => "QOI_OP_INDEX",
+            OpKind::Diff => "QOI_OP_DIFF",
+            OpKind::Luma => "QOI_OP_LUMA",
+            OpKind::Run => "QOI_OP_RUN",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One op as it was decoded, handed to the callback of `decode_with_trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TracedOp<'a> {
+    /// Where the op starts, counting from the start of the file.
+    pub offset: usize,
+    /// The op's bytes, tag first.
+    pub bytes: &'a [u8],
+    pub kind: OpKind,
+    /// The pixel the op stands for.
+    pub pixel: Pixel,
+    /// How many pixels it wrote: the length of a run, otherwise 1, and fewer once the
+    /// data goes past the end of the image.
+    pub pixels: usize,
+}
+
+/// Decodes a complete in-memory QOI file (header, pixel data and end marker). Takes
+/// anything holding the bytes, e.g. a `&[u8]`, `Vec<u8>`, `Cow<[u8]>` or `bytes::Bytes`,
+/// and only reads them, so nothing is copied.
+pub fn decode(bytes: impl AsRef<[u8]>) -> Result<DecodedImage, QoiError> {
+    decode_with_options(bytes, DecodeOptions::default())
+}
+
+/// Like `decode`, but with control over how mismatches with the header are handled.
+pub fn decode_with_options(bytes: impl AsRef<[u8]>, options: DecodeOptions) -> Result<DecodedImage, QoiError> {
+    let bytes = bytes.as_ref();
+    let header = QoiHeader::parse_with(bytes, options.lenient_header)?;
+    decode::decode_image(&header, &bytes[14..], options).map(|(image, _, _)| image)
+}
+
+/// Decodes the QOI image at the start of `bytes`, returning it along with how many
+/// bytes it took up, from the header to the end marker. Anything after the marker
+/// is left alone, so several images stored back to back can be decoded one after
+/// the other, and trailing data can be spotted by comparing with `bytes.len()`.
+pub fn decode_prefix(bytes: &[u8], options: DecodeOptions) -> Result<(DecodedImage, usize), QoiError> {
+    let header = QoiHeader::parse_with(bytes, options.lenient_header)?;
+    decode::decode_image(&header, &bytes[14..], options).map(|(image, _, consumed)| (image, consumed))
+}
+
+/// Like `decode`, but calls `progress(decoded_pixels, total_pixels)` as decoding
+/// goes, e.g. to drive a progress bar: after every 64 KiB of pixel data and once
+/// more at the end. `decoded_pixels` only ever grows.
+pub fn decode_with_progress<F: FnMut(usize, usize)>(bytes: &[u8], progress: F) -> Result<DecodedImage, QoiError> {
+    let header = QoiHeader::parse(bytes)?;
+    decode::decode_image_with_progress(&header, &bytes[14..], progress)
+}
+
+/// Like `decode`, but also counts the ops the image was made of.
+pub fn decode_with_stats(bytes: &[u8]) -> Result<(DecodedImage, OpStats), QoiError> {
+    let header = QoiHeader::parse(bytes)?;
+    decode::decode_image(&header, &bytes[14..], DecodeOptions::default()).map(|(image, stats, _)| (image, stats))
+}
+
+
+/// Like `decode`, but calls `trace` with every op as it is decoded, e.g. to list
+/// what a broken file contains up to the point where it breaks. Much slower than
+/// `decode`, it's meant for debugging.
+pub fn decode_with_trace<F: FnMut(TracedOp)>(bytes: &[u8], trace: F) -> Result<DecodedImage, QoiError> {
+    let header = QoiHeader::parse(bytes)?;
+    decode::decode_image_with_trace(&header, &bytes[14..], trace)
+}
+
+/// Like `decode`, but also says which op produced each pixel, one `OpKind` per
+/// pixel in the same order as `pixels`, e.g. to color-code them in a visualizer.
+/// Every pixel of a run is tagged `Run`.
+pub fn decode_with_op_tags(bytes: &[u8]) -> Result<(DecodedImage, Vec<OpKind>), QoiError> {
+    let image = decode(bytes)?;
+    let tags = decode::op_tags(&bytes[14..], image.pixels.len() / 4);
+    Ok((image, tags))
+}
+
+// Number of pixels in a `width` x `height` image, rejecting empty images and
+// ones whose RGBA buffer size wouldn't fit in a `usize`
+pub(crate) fn pixel_count(width: u32, height: u32) -> Result<usize, QoiError> {
+    if width == 0 || height == 0 {
+        return Err(QoiError::InvalidDimensions { width, height });
+    }
+    (width as usize)
+        .checked_mul(height as usize)
+        .filter(|count| count.checked_mul(4).is_some())
+        .ok_or(QoiError::ImageTooLarge { width, height })
+}
+
+// Index position of a pixel in the 64-entry table of previously seen pixels
+pub(crate) fn hash(pixel: &[u8; 4]) -> usize {
+    ((pixel[0] as u32 * 3
+        + pixel[1] as u32 * 5
+        + pixel[2] as u32 * 7
+        + pixel[3] as u32 * 11)
+        % 64) as usize
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use alloc::{format, vec};
+
+    use super::*;
+
+    // The 14-byte header of a file with these fields
+    pub(crate) fn header(width: u32, height: u32, channels: u8, colorspace: u8) -> Vec<u8> {
+        let mut header = b"qoif".to_vec();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&[channels, colorspace]);
+        header
+    }
+
+    // A whole file: the header of an sRGB image, `ops` and the end marker
+    pub(crate) fn file(width: u32, height: u32, channels: u8, ops: &[u8]) -> Vec<u8> {
+        [&header(width, height, channels, 0), ops, &[0, 0, 0, 0, 0, 0, 0, 1]].concat()
+    }
+
+    #[test]
+    fn parse_rejects_unknown_channel_counts() {
+        assert!(matches!(QoiHeader::parse(&header(1, 1, 2, 0)), Err(QoiError::InvalidChannels(2))));
+        assert!(matches!(QoiHeader::parse(&header(1, 1, 0, 0)), Err(QoiError::InvalidChannels(0))));
+        assert_eq!(QoiHeader::parse(&header(1, 1, 3, 0)).unwrap().channels, 3);
+        assert_eq!(QoiHeader::parse(&header(1, 1, 4, 0)).unwrap().channels, 4);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_colorspaces() {
+        assert!(matches!(QoiHeader::parse(&header(1, 1, 4, 2)), Err(QoiError::InvalidColorspace(2))));
+        assert_eq!(QoiHeader::parse(&header(1, 1, 4, 0)).unwrap().colorspace, Colorspace::Srgb);
+        assert_eq!(QoiHeader::parse(&header(1, 1, 4, 1)).unwrap().colorspace, Colorspace::Linear);
+    }
+
+    #[test]
+    fn decode_rejects_empty_and_huge_images() {
+        let empty = [header(0, 0, 4, 0), vec![0, 0, 0, 0, 0, 0, 0, 1]].concat();
+        assert!(matches!(decode(&empty), Err(QoiError::InvalidDimensions { width: 0, height: 0 })));
+        let no_rows = [header(5, 0, 4, 0), vec![0, 0, 0, 0, 0, 0, 0, 1]].concat();
+        assert!(matches!(decode(&no_rows), Err(QoiError::InvalidDimensions { width: 5, height: 0 })));
+
+        // 4.3 billion pixels, rejected before anything is allocated
+        let huge = [header(65535, 65535, 4, 0), vec![0, 0, 0, 0, 0, 0, 0, 1]].concat();
+        assert!(matches!(decode(&huge), Err(QoiError::ImageTooLarge { width: 65535, height: 65535 })));
+    }
+
+    #[test]
+    fn decode_requires_the_end_marker() {
+        // Two pixels, complete apart from the marker
+        let mut bytes = file(2, 1, 4, &[0xFE, 10, 20, 30, 0xC0]);
+        assert!(decode(&bytes).is_ok());
+        bytes.truncate(bytes.len() - 8);
+        assert!(matches!(decode(&bytes), Err(QoiError::MissingEndMarker { offset: 19 })));
+        // Only part of the marker left
+        let cut = file(2, 1, 4, &[0xFE, 10, 20, 30, 0xC0, 0, 0, 0]);
+        assert!(matches!(decode(&cut[..cut.len() - 8]), Err(QoiError::MissingEndMarker { offset: 22 })));
+    }
+
+    #[test]
+    fn parse_reads_only_the_header() {
+        let logo = QoiHeader::parse(&include_bytes!("../qoi-logo.qoi")[..14]).unwrap();
+        assert_eq!(
+            logo,
+            QoiHeader { magic: ['q', 'o', 'i', 'f'], width: 719, height: 325, channels: 4, colorspace: Colorspace::Srgb }
+        );
+        assert!(matches!(QoiHeader::parse(&header(719, 325, 4, 0)[..13]), Err(QoiError::TooSmall)));
+    }
+
+    #[test]
+    fn pixels_iter_goes_row_by_row() {
+        let image = decode(file(2, 2, 4, &[0xFE, 1, 2, 3, 0xFE, 4, 5, 6, 0xFF, 7, 8, 9, 10, 0xFE, 11, 12, 13])).unwrap();
+        let pixels: Vec<_> = image.pixels_iter().collect();
+        assert_eq!(
+            pixels,
+            [
+                Pixel { r: 1, g: 2, b: 3, a: 255 },
+                Pixel { r: 4, g: 5, b: 6, a: 255 },
+                Pixel { r: 7, g: 8, b: 9, a: 10 },
+                Pixel { r: 11, g: 12, b: 13, a: 10 },
+            ]
+        );
+        assert_eq!(image.get_pixel(0, 1), Some(pixels[2]));
+        assert_eq!(image.get_pixel(1, 1), Some(pixels[3]));
+        assert_eq!(image.get_pixel(2, 0), None);
+        assert_eq!(image.get_pixel(0, 2), None);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn open_mmap_decodes_like_decode() {
+        let mapped = QOI::open_mmap("qoi-logo.qoi").unwrap();
+        assert_eq!(mapped, decode(include_bytes!("../qoi-logo.qoi")).unwrap());
+        assert!(matches!(QOI::open_mmap("missing.qoi"), Err(QoiError::Io(_))));
+    }
+
+    #[test]
+    fn to_raw_follows_the_source_channels() {
+        let rgb = decode(file(3, 2, 3, &[0xFE, 10, 20, 30, 0xC4])).unwrap();
+        assert_eq!(rgb.to_raw().len(), 3 * 2 * 3);
+        let rgba = decode(file(3, 2, 4, &[0xFF, 10, 20, 30, 40, 0xC4])).unwrap();
+        assert_eq!(rgba.to_raw().len(), 3 * 2 * 4);
+        assert_eq!(rgba.to_raw(), rgba.pixels);
+    }
+
+    #[test]
+    fn debug_shows_the_header_but_not_the_pixels() {
+        let file = QOI::try_from(file(3, 2, 4, &[0xFE, 10, 20, 30, 0xC4])).unwrap();
+        let debug = format!("{:?}", file);
+        assert!(debug.contains("width: 3"), "{}", debug);
+        assert!(debug.contains("height: 2"), "{}", debug);
+        assert!(debug.contains("data: 13 bytes"), "{}", debug);
+    }
+
+    #[test]
+    fn try_from_embedded_bytes() {
+        static LOGO: &[u8] = include_bytes!("../qoi-logo.qoi");
+        let file = QOI::try_from(LOGO).unwrap();
+        assert_eq!((file.width, file.height, file.channels), (719, 325, 4));
+        assert_eq!(file.data(), &LOGO[14..]);
+        assert_eq!(file.decode().unwrap(), decode(LOGO).unwrap());
+        assert!(matches!(QOI::try_from(&LOGO[..10]), Err(QoiError::TooSmall)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn to_linear_f32_applies_the_srgb_curve() {
+        let mut image = decode(file(1, 1, 4, &[0xFF, 128, 0, 255, 128])).unwrap();
+        let linear = image.to_linear_f32();
+        assert!((linear[0] - 0.2158605).abs() < 1e-5, "{}", linear[0]);
+        assert_eq!(&linear[1..], [0.0, 1.0, 128.0 / 255.0]);
+
+        image.colorspace = Colorspace::Linear;
+        assert_eq!(image.to_linear_f32(), [128.0 / 255.0, 0.0, 1.0, 128.0 / 255.0]);
+    }
+
+    #[test]
+    fn progress_only_grows_up_to_the_total() {
+        // 40000 QOI_OP_RGB ops, 160 KB, so a few reports come before the last one
+        let ops: Vec<u8> = (0..40000u32).flat_map(|n| [0xFE, n as u8, (n >> 8) as u8, 0]).collect();
+        let mut reports = Vec::new();
+        decode_with_progress(&file(200, 200, 3, &ops), |decoded, total| reports.push((decoded, total))).unwrap();
+
+        assert!(reports.len() > 2, "{:?}", reports);
+        assert!(reports.windows(2).all(|pair| pair[0].0 <= pair[1].0), "{:?}", reports);
+        assert!(reports.iter().all(|&(_, total)| total == 40000));
+        assert_eq!(reports.last(), Some(&(40000, 40000)));
+    }
+
+    #[test]
+    fn downscale_keeps_every_other_pixel() {
+        let ops: Vec<u8> = (0..16).flat_map(|n| [0xFE, n, 0, 0]).collect();
+        let image = decode(file(4, 4, 3, &ops)).unwrap();
+        let small = image.downscale(2);
+        assert_eq!((small.width, small.height, small.channels), (2, 2, 3));
+        let reds: Vec<u8> = small.pixels_iter().map(|pixel| pixel.r).collect();
+        assert_eq!(reds, [0, 2, 8, 10]);
+        assert_eq!(image.downscale(3).pixels_iter().map(|pixel| pixel.r).collect::<Vec<_>>(), [0, 3, 12, 15]);
+    }
+
+    #[test]
+    fn builder_options_decode() {
+        let options = DecodeOptions::new().strict(true).premultiply(true).max_pixels(2).lenient_header(true).build();
+        assert_eq!(
+            options,
+            DecodeOptions { strict: true, premultiply: true, max_pixels: 2, lenient_header: true, require_end_marker: true }
+        );
+        let image = decode_with_options(file(2, 1, 4, &[0xFF, 255, 0, 0, 128, 0xC0]), options).unwrap();
+        assert_eq!(image.pixels, [128, 0, 0, 128, 128, 0, 0, 128]);
+        let too_large = decode_with_options(file(3, 1, 4, &[0xFE, 1, 2, 3, 0xC1]), options);
+        assert!(matches!(too_large, Err(QoiError::ImageTooLarge { width: 3, height: 1 })));
+    }
+
+    #[test]
+    fn decode_prefix_splits_concatenated_images() {
+        let first = file(2, 1, 4, &[0xFE, 10, 20, 30, 0xC0]);
+        let second = file(1, 1, 3, &[0xFE, 40, 50, 60]);
+        let both = [&first[..], &second[..]].concat();
+
+        let (image, consumed) = decode_prefix(&both, DecodeOptions::default()).unwrap();
+        assert_eq!(consumed, first.len());
+        assert_eq!(image.pixels, [10, 20, 30, 255, 10, 20, 30, 255]);
+        let (image, consumed) = decode_prefix(&both[consumed..], DecodeOptions::default()).unwrap();
+        assert_eq!(consumed, second.len());
+        assert_eq!((image.width, image.channels, image.pixels), (1, 3, vec![40, 50, 60, 255]));
+    }
+
+    #[test]
+    fn default_pixel_limit_rejects_huge_headers() {
+        // 1.6 billion pixels, 6.4 GB of RGBA, claimed by a 22-byte file
+        let huge = file(40000, 40000, 4, &[]);
+        assert!(matches!(decode(&huge), Err(QoiError::ImageTooLarge { width: 40000, height: 40000 })));
+        let options = DecodeOptions::new().max_pixels(40000 * 40000 - 1);
+        assert!(matches!(decode_with_options(&huge, options), Err(QoiError::ImageTooLarge { .. })));
+        assert!(matches!(Decoder::new().feed(&huge), Err(QoiError::ImageTooLarge { .. })));
+    }
+
+    #[test]
+    fn to_rgba16_uses_the_full_range() {
+        let image = decode(file(1, 1, 4, &[0xFF, 255, 0, 128, 1])).unwrap();
+        assert_eq!(image.to_rgba16(), [65535, 0, 128 * 257, 257]);
+    }
+
+    #[test]
+    fn op_tags_of_a_solid_image() {
+        let solid = [10, 20, 30, 255].repeat(100);
+        let (image, tags) = decode_with_op_tags(&encode_pixels(10, 10, 4, &solid).unwrap()).unwrap();
+        assert_eq!(image.pixels, solid);
+        assert_eq!(tags.len(), 100);
+        assert_eq!(tags[0], OpKind::Rgb);
+        assert!(tags[1..].iter().all(|&tag| tag == OpKind::Run));
+    }
+
+    #[test]
+    fn lenient_headers_fall_back_to_rgba_and_srgb() {
+        let mut bytes = file(1, 1, 0, &[0xFF, 10, 20, 30, 40]);
+        bytes[13] = 7;
+        assert!(matches!(decode(&bytes), Err(QoiError::InvalidChannels(0))));
+
+        let header = QoiHeader::parse_lenient(&bytes).unwrap();
+        assert_eq!((header.channels, header.colorspace), (4, Colorspace::Srgb));
+        let image = decode_with_options(&bytes, DecodeOptions::new().lenient_header(true)).unwrap();
+        assert_eq!((image.channels, image.colorspace), (4, Colorspace::Srgb));
+        assert_eq!(image.pixels, [10, 20, 30, 40]);
+
+        // The magic still has to be right
+        bytes[0] = b'Q';
+        assert!(matches!(QoiHeader::parse_lenient(&bytes), Err(QoiError::BadMagic)));
+    }
+
+    #[test]
+    fn grayscale_keeps_white_and_black() {
+        let image = decode(file(3, 1, 4, &[0xFE, 255, 255, 255, 0xFF, 0, 0, 0, 0, 0xFF, 255, 0, 0, 255])).unwrap();
+        // A pure red is its Rec.601 weight, 0.299 * 255
+        assert_eq!(image.to_grayscale(), [255, 0, 76]);
+    }
+
+    #[test]
+    fn display_sums_up_the_image() {
+        let rgb = decode(file(3, 2, 3, &[0xFE, 10, 20, 30, 0xC4])).unwrap();
+        assert_eq!(format!("{}", rgb), "DecodedImage 3x2 RGB sRGB (24 B)");
+        let logo = decode(include_bytes!("../qoi-logo.qoi")).unwrap();
+        assert_eq!(format!("{}", logo), "DecodedImage 719x325 RGBA sRGB (934.7 KB)");
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn pixel_count_is_computed_in_usize() {
+        // 2^32 pixels overflow a u32 product but not a 64-bit usize
+        assert_eq!(pixel_count(65536, 65536).unwrap(), 1 << 32);
+        assert_eq!(pixel_count(u32::MAX, 2).unwrap(), u32::MAX as usize * 2);
+        // Beyond the default limit, so rejected before allocating, not wrapped around
+        let huge = file(65536, 65536, 4, &[]);
+        assert!(matches!(decode(huge), Err(QoiError::ImageTooLarge { width: 65536, height: 65536 })));
+        // Only an RGBA size past usize::MAX is too large to count at all
+        assert!(matches!(pixel_count(u32::MAX, u32::MAX), Err(QoiError::ImageTooLarge { .. })));
+    }
+
+    #[test]
+    fn dimensions_are_big_endian() {
+        let mut bytes = header(0, 0, 4, 0);
+        bytes[4..12].copy_from_slice(&[0x00, 0x00, 0x02, 0xCF, 0x00, 0x01, 0x01, 0x45]);
+        let header = QoiHeader::parse(&bytes).unwrap();
+        assert_eq!((header.width, header.height), (719, 65861));
+    }
+
+    #[test]
+    fn with_channels_both_ways() {
+        let image = decode(file(2, 1, 4, &[0xFF, 10, 20, 30, 40, 0xFF, 50, 60, 70, 80])).unwrap();
+
+        let rgb = image.clone().with_channels(3).unwrap();
+        assert_eq!(rgb.channels, 3);
+        assert_eq!(rgb.pixels, [10, 20, 30, 255, 50, 60, 70, 255]);
+        assert_eq!(rgb.to_raw(), [10, 20, 30, 50, 60, 70]);
+
+        // Back to 4 keeps the now opaque alpha
+        let rgba = rgb.with_channels(4).unwrap();
+        assert_eq!(rgba.channels, 4);
+        assert_eq!(rgba.to_raw(), [10, 20, 30, 255, 50, 60, 70, 255]);
+        assert_eq!(image.clone().with_channels(4).unwrap(), image);
+        assert!(matches!(image.with_channels(2), Err(QoiError::InvalidChannels(2))));
+    }
+
+    #[test]
+    fn spots_premultiplied_pixels() {
+        // Every color channel at or below its alpha, and some color
+        let premultiplied = decode(file(2, 1, 4, &[0xFF, 64, 32, 0, 64, 0xFF, 100, 100, 100, 128])).unwrap();
+        assert!(premultiplied.looks_premultiplied());
+        // Red at full strength under half alpha can't be premultiplied
+        let straight = decode(file(2, 1, 4, &[0xFF, 64, 32, 0, 64, 0xFF, 255, 0, 0, 128])).unwrap();
+        assert!(!straight.looks_premultiplied());
+        let opaque = decode(file(1, 1, 4, &[0xFE, 10, 20, 30])).unwrap();
+        assert!(!opaque.looks_premultiplied());
+    }
+
+    #[test]
+    fn decodes_borrowed_and_owned_bytes_alike() {
+        let bytes = file(2, 1, 4, &[0xFE, 10, 20, 30, 0xC0]);
+        let expected = decode(&bytes[..]).unwrap();
+        assert_eq!(decode(alloc::borrow::Cow::Borrowed(&bytes[..])).unwrap(), expected);
+        assert_eq!(decode(&bytes).unwrap(), expected);
+        assert_eq!(decode(bytes).unwrap(), expected);
+    }
+
+    #[test]
+    fn utf8_bom_is_reported() {
+        let bytes = [&[0xEF, 0xBB, 0xBF][..], &file(1, 1, 4, &[0xFE, 1, 2, 3])].concat();
+        let err = decode(&bytes).unwrap_err();
+        assert!(matches!(err, QoiError::Utf8Bom));
+        assert_eq!(format!("{}", err), "File has a UTF-8 BOM; it may have been corrupted by a text tool");
+        assert!(decode(&bytes[3..]).is_ok());
+    }
+}
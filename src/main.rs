@@ -1,224 +1,677 @@
 use std::env;
 use std::error::Error;
-use std::fs::read;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[cfg(feature = "image")]
+use image::ImageFormat;
 use rayon::prelude::*;
 
-use image::{ImageBuffer, RgbaImage};
+#[cfg(feature = "image")]
+use qoi_rust::encode_dynamic_image;
+#[cfg(all(feature = "png-only", not(feature = "image")))]
+use qoi_rust::{write_png, write_png_to};
+#[cfg(feature = "base64")]
+use qoi_rust::qoi_from_base64;
+use qoi_rust::{
+    decode, decode_prefix, decode_with_options, decode_with_stats, decode_with_trace, encode_with_options, write_ppm,
+    write_tga, Colorspace, DecodeOptions, DecodedImage, EncodeOptions, OpStats, QoiHeader,
+};
+
+
+// `-` stands for stdin as an input and stdout as an output
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
 
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    if is_stdio(Path::new(path)) {
+        let mut bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    } else {
+        std::fs::read(path)
+    }
+}
 
-struct QOI {
-    name: String,
-    magic: [char; 4],
-    width: u32,
-    height: u32,
-    channels: u8,
-    colorspace: u8,
-    data: Vec<u8>,
+// Writes the pixels to `path`, in whatever format its extension names (png, jpg, bmp, tiff, ...),
+// or as PNG to stdout for `-`
+fn save_as_image(image: &DecodedImage, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // PPM and TGA are simple enough to write without going through the `image` crate
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ppm")) {
+        write_ppm(image, path)?;
+        return Ok(());
+    }
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("tga")) {
+        write_tga(image, path)?;
+        return Ok(());
+    }
+    save_with_image_crate(image, path)
 }
 
-impl QOI {
-    fn new(path: &str) -> Result<Self, Box<dyn Error>> {
-        let name = path.to_string();
-        let mut buffer = read(path).unwrap();
+#[cfg(feature = "image")]
+fn save_with_image_crate(image: &DecodedImage, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if is_stdio(path) {
+        io::stdout().lock().write_all(&image.to_png_bytes()?)?;
+        return Ok(());
+    }
 
-        if buffer.len() < 14 {
-            return Err("File too small".into());
-        }
+    let format = ImageFormat::from_path(path)
+        .ok()
+        .filter(|format| format.writing_enabled())
+        .ok_or_else(|| format!("Unsupported output format: {}", path.display()))?;
+
+    let img = image.to_dynamic_image();
+    if format == ImageFormat::Jpeg {
+        // JPEG has no alpha channel, so drop it instead of failing
+        img.to_rgb8().save_with_format(path, format)?;
+    } else {
+        img.save_with_format(path, format)?;
+    }
+    Ok(())
+}
 
-        let magic = [buffer[0] as char, buffer[1] as char, buffer[2] as char, buffer[3] as char];
+// With only the `png` crate, PNG is the one format besides PPM and TGA
+#[cfg(all(feature = "png-only", not(feature = "image")))]
+fn save_with_image_crate(image: &DecodedImage, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if is_stdio(path) {
+        write_png_to(image, io::stdout().lock())?;
+    } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+        write_png(image, path)?;
+    } else {
+        return Err(format!(
+            "Unsupported output format: {} (built with `png-only`, only .png, .ppm, .tga and --raw are available)",
+            path.display()
+        )
+        .into());
+    }
+    Ok(())
+}
 
-        if magic != ['q', 'o', 'i', 'f'] {
-            return Err("Invalid magic number".into());
-        }
+#[cfg(not(any(feature = "image", feature = "png-only")))]
+fn save_with_image_crate(_image: &DecodedImage, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Err(format!(
+        "Unsupported output format: {} (built without the `image` feature, only .ppm, .tga and --raw are available)",
+        path.display()
+    )
+    .into())
+}
+
+// Writes the bare pixels to `path`, or to stdout for `-`
+fn save_as_raw(image: &DecodedImage, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if is_stdio(path) {
+        io::stdout().lock().write_all(&image.to_raw())?;
+    } else {
+        image.save_raw(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "base64")]
+fn from_base64(text: &[u8]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    Ok(qoi_from_base64(text)?)
+}
+
+#[cfg(not(feature = "base64"))]
+fn from_base64(_text: &[u8]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    Err("--base64 needs a build with the `base64` feature".into())
+}
+
+// Command line switches that apply to every file
+#[derive(Debug, Clone, Copy, Default)]
+struct Flags {
+    raw: bool,   // --raw: write the bare decoded pixels instead of an image file
+    stats: bool, // --stats: print how many of each op the file uses
+    json: bool,  // --json: print `info` as one JSON object per file
+    force: bool, // --force: replace output files that already exist
+    lenient: bool, // --lenient: decode files whose channel count or colorspace is out of range
+    debug: bool,   // --debug: list every op of the file instead of converting it
+    channels: Option<u8>, // --channels 3|4: write RGB or RGBA, whatever the header says
+    base64: bool,  // --base64: the input files hold base64 text around the QOI data
+    ratio: bool,   // --ratio: add the raw pixel size and the compression ratio to `info`
+}
 
-        let width = (buffer[4] as u32) << 24 | (buffer[5] as u32) << 16 | (buffer[6] as u32) << 8 | buffer[7] as u32;
-        let height = (buffer[8] as u32) << 24 | (buffer[9] as u32) << 16 | (buffer[10] as u32) << 8 | buffer[11] as u32;
-        let channels = buffer[12];
-        let colorspace = buffer[13];
-        let data = buffer.split_off(14);
-
-        Ok(Self{
-            name: name,
-            magic: magic,
-            width: width,
-            height: height,
-            channels: channels,
-            colorspace: colorspace,
-            data: data,
+impl Flags {
+    // Pulls the switches out of `args`, leaving only the paths and subcommands. None
+    // if a switch has a bad value
+    fn take_from(args: &mut Vec<String>) -> Option<Self> {
+        let channels = match args.iter().position(|arg| arg == "--channels") {
+            Some(at) => {
+                let value = args.get(at + 1).map(String::as_str);
+                let channels = match value {
+                    Some("3") => 3,
+                    Some("4") => 4,
+                    _ => return None,
+                };
+                args.drain(at..at + 2);
+                Some(channels)
+            }
+            None => None,
+        };
+        let mut take = |flag: &str| {
+            let found = args.iter().any(|arg| arg == flag);
+            args.retain(|arg| arg != flag);
+            found
+        };
+        Some(Self {
+            raw: take("--raw"),
+            stats: take("--stats"),
+            json: take("--json"),
+            force: take("--force"),
+            lenient: take("--lenient"),
+            debug: take("--debug"),
+            base64: take("--base64"),
+            ratio: take("--ratio"),
+            channels,
         })
     }
 }
 
+// Refuses to replace an existing file unless --force was given
+fn check_overwrite(output: &Path, force: bool) -> Result<(), String> {
+    if !force && !is_stdio(output) && output.exists() {
+        return Err(format!("{} already exists, pass --force to overwrite it", output.display()));
+    }
+    Ok(())
+}
 
-fn decode_pixels(file: &mut QOI) -> Result<Vec<u8>, Box<dyn Error>> {
-    let mut index = [[0u8; 4]; 64];
-    let mut pixels = Vec::with_capacity((file.width * file.height * 4) as usize);     
-    let mut prev_pixel = [0u8, 0u8, 0u8, 255u8]; // Start with a black pixel
+fn format_stats(stats: &OpStats) -> String {
+    let rows = [
+        ("QOI_OP_RGB", stats.rgb),
+        ("QOI_OP_RGBA", stats.rgba),
+        ("QOI_OP_INDEX", stats.index),
+        ("QOI_OP_DIFF", stats.diff),
+        ("QOI_OP_LUMA", stats.luma),
+        ("QOI_OP_RUN", stats.run),
+        ("run pixels", stats.run_pixels),
+    ];
+    rows.iter()
+        .map(|(name, count)| format!("  {:<14}{:>10}", name, count))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-    let mut i = 0;
-    while i < file.data.len() {
-        if i + 8 <= file.data.len() && &file.data[i..i + 8] == &[0, 0, 0, 0, 0, 0, 0, 1] {
-            break; // End of file marker
-        }
+// Decodes one QOI file to `output`, returning a summary of its header to print
+fn decode_file(path: &str, output: &Path, flags: Flags) -> Result<String, Box<dyn Error + Send + Sync>> {
+    check_overwrite(output, flags.force)?;
+    let mut bytes = read_input(path)?;
+    if flags.base64 {
+        bytes = from_base64(&bytes)?;
+    }
+    let header = match flags.lenient {
+        true => QoiHeader::parse_lenient(&bytes)?,
+        false => QoiHeader::parse(&bytes)?,
+    };
+    if header.channels != bytes[12] || header.colorspace as u8 != bytes[13] {
+        eprintln!(
+            "warning: {}: invalid channels {} / colorspace {}, decoding as {} / {:?}",
+            path, bytes[12], bytes[13], header.channels, header.colorspace
+        );
+        // Repair the header in our copy, so decoding sees the same values
+        bytes[12] = header.channels;
+        bytes[13] = header.colorspace as u8;
+    }
 
-        let pixel = file.data[i];
+    let (mut image, stats) = decode_with_stats(&bytes)?;
+    if stats.dropped_pixels > 0 {
+        eprintln!(
+            "warning: {}: {} pixels past the {}x{} in the header were dropped, the file may be corrupt",
+            path, stats.dropped_pixels, image.width, image.height
+        );
+    }
+    if let Some(channels) = flags.channels {
+        image = image.with_channels(channels)?;
+    }
+    let mut info = format!("{}: {} from {} bytes", path, image, bytes.len());
+    if flags.stats {
+        info = format!("{}\n{}", info, format_stats(&stats));
+    }
+    if flags.raw {
+        save_as_raw(&image, output)?;
+    } else {
+        save_as_image(&image, output)?;
+    }
+    Ok(info)
+}
 
-        match pixel {
-            0b11111110 => { // QOI_OP_RGB
-                // println!("QOI_OP_RGB at index {}", i);
-                i += handle_rgb(&mut file.data, &mut pixels, &mut prev_pixel, i)?;
-            }
-            0b11111111 => { // QOI_OP_RGBA
-                // println!("QOI_OP_RGBA at index {}", i);
-                i += handle_rgba(&mut file.data, &mut pixels, &mut prev_pixel, i)?;
-            }
-            _ if (pixel >> 6) == 0b00 => { // QOI_OP_INDEX
-                // println!("QOI_OP_INDEX at index {}", i);
-                i += handle_index(&mut index, &mut pixels, &mut prev_pixel, pixel)?;
-            }
-            _ if (pixel >> 6) == 0b01 => { // QOI_OP_DIFF
-                // println!("QOI_OP_DIFF at index {}", i);
-                i += handle_diff(&mut pixels, &mut prev_pixel, pixel)?;
-            }
-            _ if (pixel >> 6) == 0b10 => { // QOI_OP_LUMA
-                // println!("QOI_OP_LUMA at index {}", i);
-                i += handle_luma(&mut file.data, &mut pixels, &mut prev_pixel, i)?;
-            }
-            _ if (pixel >> 6) == 0b11 => { // QOI_OP_RUN
-                // println!("QOI_OP_RUN at index {}", i);
-                i += handle_run(&mut pixels, &mut prev_pixel, pixel, file.width, file.height)?;
+// Decodes every file strictly without writing anything, reporting the first problem
+// in each, e.g. to check assets in CI
+fn verify_files(paths: &[String]) -> Result<(), Box<dyn Error>> {
+    let strict = DecodeOptions::new().strict(true).build();
+    let results: Vec<Result<(), Box<dyn Error + Send + Sync>>> = paths
+        .par_iter()
+        .map(|path| {
+            decode_with_options(&read_input(path)?, strict)?;
+            Ok(())
+        })
+        .collect();
+
+    let mut failed = 0;
+    for (path, result) in paths.iter().zip(results) {
+        match result {
+            Ok(()) => println!("{}: OK", path),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                failed += 1;
             }
-            _ => return Err(format!("Unknown QOI operation: {:08b}", pixel).into()),
         }
+    }
 
-        // Update the index after each pixel
-        let hash = ((prev_pixel[0] as u32 * 3
-            + prev_pixel[1] as u32 * 5
-            + prev_pixel[2] as u32 * 7
-            + prev_pixel[3] as u32 * 11)
-            % 64) as usize;
-        index[hash] = prev_pixel;
-        // println!("Updated index at hash {}: {:?}", hash, prev_pixel);
+    if failed > 0 {
+        return Err(format!("{} of {} files failed verification", failed, paths.len()).into());
     }
+    Ok(())
+}
 
-    let expected_len = (file.width as usize) * (file.height as usize) * 4; // RGBA requires 4 bytes per pixel
-    if pixels.len() > expected_len {
-        pixels.truncate(expected_len); // Trim extra pixels
+// Whether the RGBA file at `path` needs its alpha channel, and if not, how much
+// smaller it gets re-encoded as RGB. QOI writes no alpha for opaque pixels anyway,
+// so that is 0 for files from encoders that follow the spec, and only the ones that
+// wrote a QOI_OP_RGBA where a 3-channel op would have done shrink
+fn alpha_savings(path: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let bytes = read_input(path)?;
+    let image = decode(&bytes)?;
+    if image.channels == 3 {
+        return Ok(format!("{}: already RGB", path));
     }
-    if pixels.len() != expected_len {
-        return Err(format!("Pixel data length mismatch: expected {}, got {}", expected_len, pixels.len()).into());
+    let translucent = image.pixels.chunks_exact(4).filter(|pixel| pixel[3] != 255).count();
+    if translucent > 0 {
+        let total = image.pixels.len() / 4;
+        return Ok(format!("{}: alpha is used by {} of {} pixels, keep RGBA", path, translucent, total));
     }
-    Ok(pixels)
+    let options = EncodeOptions { channels: Some(3), colorspace: image.colorspace, ..Default::default() };
+    let rgb = encode_with_options(image.width, image.height, 4, &image.pixels, options)?;
+    Ok(format!(
+        "{}: fully opaque, {} bytes as RGB instead of {}, saves {} bytes",
+        path,
+        rgb.len(),
+        bytes.len(),
+        bytes.len().saturating_sub(rgb.len())
+    ))
 }
 
-
-fn handle_rgb(data: &[u8], pixels: &mut Vec<u8>, prev_pixel: &mut [u8; 4], i: usize) -> Result<usize, Box<dyn std::error::Error>> {
-    if i + 3 >= data.len() {
-        return Err("Unexpected end of file for QOI_OP_RGB".into());
+// Reports for every file whether re-encoding it as RGB would make it smaller
+fn print_alpha_savings(paths: &[String]) -> Result<(), Box<dyn Error>> {
+    let results: Vec<_> = paths.par_iter().map(|path| alpha_savings(path)).collect();
+
+    let mut failed = 0;
+    for (path, result) in paths.iter().zip(results) {
+        match result {
+            Ok(report) => println!("{}", report),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                failed += 1;
+            }
+        }
     }
 
-    prev_pixel[0] = data[i + 1]; // R
-    prev_pixel[1] = data[i + 2]; // G
-    prev_pixel[2] = data[i + 3]; // B
-    prev_pixel[3] = 255; // Alpha for RGB
+    if failed > 0 {
+        return Err(format!("{} of {} files failed to decode", failed, paths.len()).into());
+    }
+    Ok(())
+}
 
-    pixels.extend_from_slice(prev_pixel); // Add the pixel to the pixels list
-    Ok(4) // The length of the RGB data is 4 bytes (1 byte for the operation + 3 bytes for RGB)
+// The header of the file at `path` and the size of the whole file
+fn read_header(path: &str) -> Result<(QoiHeader, usize), Box<dyn Error>> {
+    let bytes = read_input(path)?;
+    Ok((QoiHeader::parse(&bytes)?, bytes.len()))
 }
 
+// Prints the header of every file without decoding the pixels, as text or as JSON
+// for scripts. With --ratio also the size of the raw pixels the header describes and
+// how many times smaller the file is
+fn print_info(paths: &[String], flags: Flags) -> Result<(), Box<dyn Error>> {
+    let mut failed = 0;
+    for path in paths {
+        let (header, file_size) = match read_header(path) {
+            Ok(info) => info,
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                failed += 1;
+                continue;
+            }
+        };
+        let colorspace = match header.colorspace {
+            Colorspace::Srgb => "srgb",
+            Colorspace::Linear => "linear",
+        };
+        let raw_size = header.width as u64 * header.height as u64 * header.channels as u64;
+        let ratio = raw_size as f64 / file_size as f64;
+        if flags.json {
+            let ratio = match flags.ratio {
+                true => format!(",\"raw_size\":{},\"ratio\":{:.2}", raw_size, ratio),
+                false => String::new(),
+            };
+            println!(
+                "{{\"width\":{},\"height\":{},\"channels\":{},\"colorspace\":\"{}\",\"file_size\":{}{}}}",
+                header.width, header.height, header.channels, colorspace, file_size, ratio
+            );
+        } else {
+            let ratio = match flags.ratio {
+                true => format!(", {} bytes raw, ratio {:.2}:1", raw_size, ratio),
+                false => String::new(),
+            };
+            println!(
+                "{}: {}x{}, {} channels, {}, {} bytes{}",
+                path, header.width, header.height, header.channels, colorspace, file_size, ratio
+            );
+        }
+    }
 
-fn handle_rgba(data: &[u8], pixels: &mut Vec<u8>, prev_pixel: &mut [u8; 4], i: usize) -> Result<usize, Box<dyn std::error::Error>> {
-    if i + 4 >= data.len() {
-        return Err("Unexpected end of file for QOI_OP_RGBA".into());
+    if failed > 0 {
+        return Err(format!("{} of {} files could not be read", failed, paths.len()).into());
     }
+    Ok(())
+}
 
-    prev_pixel[0] = data[i + 1]; // R
-    prev_pixel[1] = data[i + 2]; // G
-    prev_pixel[2] = data[i + 3]; // B
-    prev_pixel[3] = data[i + 4]; // A
+// Lists every op in the file with its offset, bytes and the pixel it stands for, up
+// to the end marker or the first error, e.g. to find where a broken file goes wrong
+fn debug_file(path: &str) -> Result<(), Box<dyn Error>> {
+    let bytes = read_input(path)?;
+    let header = QoiHeader::parse(&bytes)?;
+    println!(
+        "{}: {}x{}, {} channels, {:?}",
+        path, header.width, header.height, header.channels, header.colorspace
+    );
 
-    pixels.extend_from_slice(prev_pixel); // Add the pixel to the pixels list
-    Ok(5) // The length of the RGBA data is 5 bytes (1 byte for the operation + 4 bytes for RGBA)
+    let image = decode_with_trace(&bytes, |op| {
+        let hex: Vec<String> = op.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        println!(
+            "{:>8}  {:<14} {:<12} x{:<2} #{:02x}{:02x}{:02x}{:02x}",
+            op.offset,
+            hex.join(" "),
+            op.kind.to_string(),
+            op.pixels,
+            op.pixel.r,
+            op.pixel.g,
+            op.pixel.b,
+            op.pixel.a
+        );
+    })?;
+    println!("End marker reached, {} pixels", image.pixels.len() / 4);
+    Ok(())
 }
 
-fn handle_index(index: &mut [[u8; 4]; 64], pixels: &mut Vec<u8>, prev_pixel: &mut [u8; 4], pixel: u8) -> Result<usize, Box<dyn std::error::Error>> {
-    let idx = pixel as usize;
-    if idx >= index.len() {
-        return Err("Index out of bounds for QOI_OP_INDEX".into());
+// Splits a file of QOI images stored back to back, e.g. the frames of an animation,
+// into one numbered file per frame in `output`: 0000.png, 0001.png and so on. Frames
+// may differ in size.
+fn split_frames(path: &str, output: &Path, flags: Flags, extension: &str) -> Result<(), Box<dyn Error>> {
+    let bytes = read_input(path)?;
+    std::fs::create_dir_all(output)?;
+
+    let mut rest = &bytes[..];
+    let mut frames = 0;
+    while !rest.is_empty() {
+        let offset = bytes.len() - rest.len();
+        let (image, consumed) =
+            decode_prefix(rest, DecodeOptions::default()).map_err(|err| format!("frame {} at offset {}: {}", frames, offset, err))?;
+        let image = match flags.channels {
+            Some(channels) => image.with_channels(channels)?,
+            None => image,
+        };
+        let target = output.join(format!("{:04}.{}", frames, extension));
+        check_overwrite(&target, flags.force)?;
+        if flags.raw {
+            image.save_raw(&target)?;
+        } else {
+            save_as_image(&image, &target).map_err(|err| err.to_string())?;
+        }
+        println!("Saved {}x{} frame as {}", image.width, image.height, target.display());
+        rest = &rest[consumed..];
+        frames += 1;
     }
-    *prev_pixel = index[idx];
-    pixels.extend_from_slice(prev_pixel);
-    Ok(1)
+    println!("Split {} into {} frames", path, frames);
+    Ok(())
 }
 
-fn handle_diff(pixels: &mut Vec<u8>, prev_pixel: &mut [u8; 4], pixel: u8) -> Result<usize, Box<dyn std::error::Error>> {
-    let dr = ((pixel >> 4) & 0x03).wrapping_sub(2);
-    let dg = ((pixel >> 2) & 0x03).wrapping_sub(2);
-    let db = (pixel & 0x03).wrapping_sub(2);
-
-    prev_pixel[0] = prev_pixel[0].wrapping_add(dr); // Apply the diff to the previous red channel
-    prev_pixel[1] = prev_pixel[1].wrapping_add(dg); // Apply the diff to the previous green channel
-    prev_pixel[2] = prev_pixel[2].wrapping_add(db); // Apply the diff to the previous blue channel
+fn is_qoi(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("qoi"))
+}
 
-    pixels.extend_from_slice(prev_pixel); // Add the updated pixel to the list
-    Ok(1) // Only 1 byte for QOI_OP_DIFF
+// Finds every .qoi file below `input`, paired with where it goes below `output`: the
+// same relative path with `extension`. Returns the jobs and how many files were skipped.
+fn dir_jobs(input: &Path, output: &Path, extension: &str) -> io::Result<(Vec<(String, PathBuf)>, usize)> {
+    let mut jobs = Vec::new();
+    let mut skipped = 0;
+    let mut dirs = vec![input.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_qoi(&path) {
+                let relative = path.strip_prefix(input).unwrap_or(&path);
+                let target = output.join(relative).with_extension(extension);
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                jobs.push((path.to_string_lossy().into_owned(), target));
+            } else {
+                skipped += 1;
+            }
+        }
+    }
+    jobs.sort();
+    Ok((jobs, skipped))
 }
 
-fn handle_luma(data: &[u8], pixels: &mut Vec<u8>, prev_pixel: &mut [u8; 4], i: usize) -> Result<usize, Box<dyn std::error::Error>> {
-    if i + 1 >= data.len() {
-        return Err("Unexpected end of file for QOI_OP_LUMA".into());
+// Loads any image the `image` crate understands and writes it out as QOI, keeping
+// alpha only if the source has it and some pixel isn't opaque
+#[cfg(feature = "image")]
+fn encode_image(input: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let img = image::open(input)?;
+    let bits = img.color().bits_per_pixel() / u16::from(img.color().channel_count());
+    if bits > 8 {
+        eprintln!(
+            "warning: {} has {} bits per channel, QOI only stores 8, so it was reduced to 8",
+            input.display(),
+            bits
+        );
     }
-    let vg = (data[i] & 0b00111111).wrapping_sub(32);
-    let second_byte = data[i + 1];
-    let dr_dg = ((second_byte >> 4) & 0b1111).wrapping_sub(8);
-    let db_dg = (second_byte & 0b1111).wrapping_sub(8);
+    let bytes = encode_dynamic_image(&img, Colorspace::Srgb)?;
+    std::fs::write(output, bytes)?;
+    Ok(())
+}
 
-    prev_pixel[0] = prev_pixel[0].wrapping_add((vg as i8 + dr_dg as i8) as u8);
-    prev_pixel[1] = prev_pixel[1].wrapping_add(vg as i8 as u8);
-    prev_pixel[2] = prev_pixel[2].wrapping_add((vg as i8 + db_dg as i8) as u8);
-    pixels.extend_from_slice(prev_pixel);
-    Ok(2)
+#[cfg(not(feature = "image"))]
+fn encode_image(_input: &Path, _output: &Path) -> Result<(), Box<dyn Error>> {
+    Err("Encoding needs the `image` feature to read the input".into())
 }
 
-fn handle_run(pixels: &mut Vec<u8>, prev_pixel: &mut [u8; 4], pixel: u8, width: u32, height: u32) -> Result<usize, Box<dyn std::error::Error>> {
-    let run_length = (pixel & 0x3F) + 1;
-    for _ in 0..run_length {
-        pixels.extend_from_slice(prev_pixel);
-    }
-    Ok(1) // We need to increment `i` by 1 since `QOI_OP_RUN` only takes 1 byte
+fn print_usage() {
+    eprintln!("Usage: cargo run /path/to/image.qoi [/path/to/output.png|jpg|bmp|tiff|tga]");
+    eprintln!("       cargo run /path/to/a.qoi /path/to/b.qoi ...");
+    eprintln!("       cargo run -- --raw /path/to/image.qoi [/path/to/output.raw]");
+    eprintln!("       cargo run -- --convert-dir /path/to/assets /path/to/out");
+    eprintln!("       cargo run -- --stats /path/to/image.qoi");
+    eprintln!("       cat image.qoi | cargo run - > image.png");
+    eprintln!("       cargo run encode /path/to/image.png [/path/to/output.qoi]");
+    eprintln!("       cargo run verify /path/to/a.qoi /path/to/b.qoi ...");
+    eprintln!("       cargo run info [--json] [--ratio] /path/to/a.qoi /path/to/b.qoi ...");
+    eprintln!("       cargo run alpha /path/to/a.qoi /path/to/b.qoi ...");
+    eprintln!("       cargo run -- --lenient /path/to/broken.qoi");
+    eprintln!("       cargo run frames /path/to/animation.qoi /path/to/frames");
+    eprintln!("       cargo run -- --debug /path/to/image.qoi");
+    eprintln!("       cargo run -- --channels 3|4 /path/to/image.qoi [/path/to/output.png]");
+    eprintln!("       cargo run --features base64 -- --base64 /path/to/image.b64 /path/to/output.png");
+    eprintln!("Existing output files are only replaced with --force");
 }
- 
-fn save_as_image(file: &QOI, pixels: &[u8]) -> Result<(), Box<dyn Error>> {
-    let width = file.width;
-    let height = file.height;
 
-    let img: RgbaImage = ImageBuffer::from_raw(width, height, pixels.to_vec())
-        .ok_or("Failed to create image buffer")?;
+// Wrong arguments: the usage goes to stderr and the exit code is 2, like other
+// command line tools, so scripts can tell it from a failed conversion (1)
+fn usage_error() -> ExitCode {
+    print_usage();
+    ExitCode::from(2)
+}
 
-    img.save("output.png")?;
-    Ok(())
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: cargo run /path/to/image.qoi");
-        return Ok(());
+fn run() -> Result<ExitCode, Box<dyn Error>> {
+    let mut args: Vec<_> = env::args().collect();
+    let Some(flags) = Flags::take_from(&mut args) else {
+        return Ok(usage_error());
+    };
+    let extension = match flags.raw {
+        true => "raw",
+        // Without the `image` or `png` crate PPM is the only image format left
+        false if cfg!(any(feature = "image", feature = "png-only")) => "png",
+        false => "ppm",
+    };
+
+    if args.get(1).map(String::as_str) == Some("encode") {
+        if args.len() != 3 && args.len() != 4 {
+            return Ok(usage_error());
+        }
+
+        let input = Path::new(&args[2]);
+        let output = match args.get(3) {
+            Some(output) => PathBuf::from(output),
+            None => input.with_extension("qoi"),
+        };
+        check_overwrite(&output, flags.force)?;
+        encode_image(input, &output)?;
+        println!("Saved image as {}", output.display());
+        return Ok(ExitCode::SUCCESS);
     }
 
-    let path = &args[1];
-    let mut file = QOI::new(path)?;
+    if args.get(1).map(String::as_str) == Some("verify") {
+        if args.len() < 3 {
+            return Ok(usage_error());
+        }
+        verify_files(&args[2..])?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    println!(
-        "name: {}, magic: {:?}, width: {}, height: {}, channels: {}, colorspace: {}, data: {}",
-        file.name, file.magic, file.width, file.height, file.channels, file.colorspace, file.data.len()
-    );
-    let pixels = decode_pixels(&mut file)?;
-    save_as_image(&file, &pixels)?;
+    if args.get(1).map(String::as_str) == Some("frames") {
+        if args.len() != 4 {
+            return Ok(usage_error());
+        }
+        split_frames(&args[2], Path::new(&args[3]), flags, extension)?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    save_as_image(&file, &pixels)?;
-    println!("Saved image as output.png");
-    Ok(())
-}
+    if args.get(1).map(String::as_str) == Some("alpha") {
+        if args.len() < 3 {
+            return Ok(usage_error());
+        }
+        print_alpha_savings(&args[2..])?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.get(1).map(String::as_str) == Some("info") {
+        if args.len() < 3 {
+            return Ok(usage_error());
+        }
+        print_info(&args[2..], flags)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.len() < 2 {
+        return Ok(usage_error());
+    }
+
+    if flags.debug {
+        for path in &args[1..] {
+            debug_file(path)?;
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut skipped = None;
+    let jobs: Vec<(String, PathBuf)> = if args[1] == "--convert-dir" {
+        if args.len() != 4 {
+            return Ok(usage_error());
+        }
+        let (jobs, count) = dir_jobs(Path::new(&args[2]), Path::new(&args[3]), extension)?;
+        skipped = Some(count);
+        jobs
+    } else {
+        // A single input may name its output, otherwise every argument is an input
+        // written next to itself: foo.qoi -> foo.png (or foo.raw). Input read from stdin goes to
+        // stdout unless told otherwise.
+        let inputs = &args[1..];
+        if inputs.len() == 2 && !is_qoi(&inputs[1]) {
+            vec![(inputs[0].clone(), PathBuf::from(&inputs[1]))]
+        } else {
+            inputs
+                .iter()
+                .map(|path| match is_stdio(Path::new(path)) {
+                    true => (path.clone(), PathBuf::from("-")),
+                    false => (path.clone(), Path::new(path).with_extension(extension)),
+                })
+                .collect()
+        }
+    };
+
+    // Files are independent, so decode them all at once and only report at the
+    // end, so one bad file doesn't stop the rest
+    let results: Vec<_> = jobs
+        .par_iter()
+        .map(|(path, output)| decode_file(path, output, flags))
+        .collect();
+
+    let mut failed = 0;
+    for ((path, output), result) in jobs.iter().zip(results) {
+        match result {
+            // Keep stdout clean when the image itself is written there
+            Ok(info) if is_stdio(output) => eprintln!("{}", info),
+            Ok(info) => {
+                println!("{}", info);
+                println!("Saved image as {}", output.display());
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                failed += 1;
+            }
+        }
+    }
 
+    if let Some(skipped) = skipped {
+        println!(
+            "Converted {} of {} QOI files, {} failed, {} other files skipped",
+            jobs.len() - failed,
+            jobs.len(),
+            failed,
+            skipped
+        );
+    }
 
+    if failed > 0 {
+        return Err(format!("{} of {} files failed to decode", failed, jobs.len()).into());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(all(test, feature = "image"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_format_follows_the_extension() {
+        let pixels = [255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 9, 9, 9, 255];
+        let image = decode(qoi_rust::encode_pixels(2, 2, 4, &pixels).unwrap()).unwrap();
+        let dir = env::temp_dir();
+
+        let bmp = dir.join(format!("qoi-rust-test-{}.bmp", std::process::id()));
+        save_as_image(&image, &bmp).unwrap();
+        let read = image::open(&bmp).unwrap().to_rgba8();
+        std::fs::remove_file(&bmp).unwrap();
+        assert_eq!(read.dimensions(), (2, 2));
+        assert_eq!(read.into_raw(), image.pixels);
+
+        let unknown = dir.join(format!("qoi-rust-test-{}.xyz", std::process::id()));
+        let err = save_as_image(&image, &unknown).unwrap_err();
+        assert!(err.to_string().starts_with("Unsupported output format"), "{}", err);
+        assert!(!unknown.exists());
+    }
+}
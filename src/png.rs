@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use png::{BitDepth, ColorType, Encoder, EncodingError};
+
+use crate::{DecodedImage, QoiError};
+
+/// Writes `image` to `path` as an 8-bit PNG, RGB for 3-channel images and RGBA
+/// otherwise, through the small `png` crate instead of all of `image`.
+pub fn write_png(image: &DecodedImage, path: impl AsRef<Path>) -> Result<(), QoiError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_png_to(image, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Like `write_png`, but to any writer, e.g. stdout or a network stream.
+pub fn write_png_to(image: &DecodedImage, out: impl Write) -> Result<(), QoiError> {
+    let color = if image.channels == 3 { ColorType::Rgb } else { ColorType::Rgba };
+    let mut encoder = Encoder::new(out, image.width, image.height);
+    encoder.set_color(color);
+    encoder.set_depth(BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&image.to_raw()).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+    Ok(())
+}
+
+// The `png` crate reports I/O errors and its own problems in one type
+fn png_error(err: EncodingError) -> QoiError {
+    match err {
+        EncodingError::IoError(err) => QoiError::Io(err),
+        err => QoiError::Io(io::Error::other(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode;
+    use crate::tests::file;
+
+    #[test]
+    fn writes_a_png_that_reads_back() {
+        let image = decode(file(3, 2, 3, &[0xFE, 10, 20, 30, 0xC1, 0xFE, 1, 2, 3, 0xC1])).unwrap();
+        let mut bytes = Vec::new();
+        write_png_to(&image, &mut bytes).unwrap();
+        assert!(bytes.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height, info.color_type), (3, 2, ColorType::Rgb));
+        assert_eq!(&pixels[..info.buffer_size()], image.to_raw());
+
+        let path = std::env::temp_dir().join(format!("qoi-rust-test-{}.png", std::process::id()));
+        write_png(&image, &path).unwrap();
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, bytes);
+    }
+}
@@ -0,0 +1,40 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::{DecodedImage, QoiError};
+
+/// Writes `image` to `path` as a binary PPM (P6): a `P6\n{width} {height}\n255\n`
+/// header followed by the RGB bytes, row by row. PPM has no alpha channel, so
+/// alpha is dropped. Needs nothing beyond std.
+pub fn write_ppm(image: &DecodedImage, path: impl AsRef<Path>) -> Result<(), QoiError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P6\n{} {}\n255\n", image.width, image.height)?;
+    for pixel in image.pixels.chunks_exact(4) {
+        out.write_all(&pixel[..3])?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode;
+    use crate::tests::file;
+
+    #[test]
+    fn header_reads_back_as_the_dimensions() {
+        let image = decode(file(3, 2, 4, &[0xFF, 10, 20, 30, 40, 0xC4])).unwrap();
+        let path = std::env::temp_dir().join(format!("qoi-rust-test-{}.ppm", std::process::id()));
+        write_ppm(&image, &path).unwrap();
+        let ppm = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let text = String::from_utf8_lossy(&ppm);
+        let fields: Vec<&str> = text.split_ascii_whitespace().take(4).collect();
+        assert_eq!(fields, ["P6", "3", "2", "255"]);
+        let header_len = "P6\n3 2\n255\n".len();
+        assert_eq!(&ppm[header_len..], [10, 20, 30].repeat(6));
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::{DecodedImage, QoiError};
+
+/// Writes `image` to `path` as an uncompressed 32-bit TGA, alpha included. TGA stores
+/// pixels as BGRA with the bottom row first, so the rows are written in reverse. Like
+/// `write_ppm` it needs nothing beyond std. TGA's dimensions are 16-bit, so images
+/// wider or taller than 65535 pixels are rejected as `ImageTooLarge`.
+pub fn write_tga(image: &DecodedImage, path: impl AsRef<Path>) -> Result<(), QoiError> {
+    let too_large = || QoiError::ImageTooLarge { width: image.width, height: image.height };
+    let width = u16::try_from(image.width).map_err(|_| too_large())?;
+    let height = u16::try_from(image.height).map_err(|_| too_large())?;
+
+    let mut header = [0u8; 18];
+    header[2] = 2; // Uncompressed true-color
+    header[12..14].copy_from_slice(&width.to_le_bytes());
+    header[14..16].copy_from_slice(&height.to_le_bytes());
+    header[16] = 32; // Bits per pixel
+    header[17] = 8; // Alpha bits, and the origin bits left at bottom-left
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&header)?;
+    for row in image.pixels.chunks_exact(width as usize * 4).rev() {
+        for pixel in row.chunks_exact(4) {
+            out.write_all(&[pixel[2], pixel[1], pixel[0], pixel[3]])?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode;
+    use crate::tests::file;
+
+    #[test]
+    fn header_holds_the_dimensions_and_32_bits() {
+        let image = decode(file(3, 2, 4, &[0xFF, 10, 20, 30, 40, 0xC1, 0xFE, 1, 2, 3, 0xC1])).unwrap();
+        let path = std::env::temp_dir().join(format!("qoi-rust-test-{}.tga", std::process::id()));
+        write_tga(&image, &path).unwrap();
+        let tga = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(u16::from_le_bytes([tga[12], tga[13]]), 3);
+        assert_eq!(u16::from_le_bytes([tga[14], tga[15]]), 2);
+        assert_eq!(tga[16], 32);
+        assert_eq!(tga.len(), 18 + 3 * 2 * 4);
+        // Bottom row first, as BGRA
+        assert_eq!(tga[18..22], [3, 2, 1, 40]);
+        assert_eq!(tga[30..34], [30, 20, 10, 40]);
+    }
+}
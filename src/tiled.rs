@@ -0,0 +1,157 @@
+// A container for images encoded as independent bands of rows, so that several cores
+// can encode and decode one image at once. Each band is a complete QOI file of its
+// own, which costs some compression: the index and the previous pixel start over at
+// every band. The layout, all integers big-endian:
+//
+//   "qoit"                         4 bytes
+//   width, height                  u32 each, of the whole image
+//   channels, colorspace           u8 each, as in a QOI header
+//   tile_rows                      u32, rows per band; the last band may be shorter
+//   tile count                     u32
+//   tile count x byte length       u64 each
+//   the bands                      one QOI file after the other, top to bottom
+
+use alloc::vec::Vec;
+use std::io;
+
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+use crate::{decode, encode_with_options, pixel_count, DecodedImage, EncodeOptions, QoiError, QoiHeader, DEFAULT_MAX_PIXELS};
+
+const MAGIC: &[u8; 4] = b"qoit";
+const HEADER_LEN: usize = 22; // Everything before the tile lengths
+
+/// Encodes `img` as bands of `tile_rows` rows each, in parallel on `threads` threads
+/// (0 for one per core), in the container format described in `src/tiled.rs`. Every
+/// band starts without any previous pixels to refer to, so the result is somewhat
+/// larger than `encode_pixels` would write; fewer, taller bands lose less. Decode it
+/// with `decode_tiled`.
+pub fn encode_tiled(img: &DecodedImage, tile_rows: u32, threads: usize) -> Result<Vec<u8>, QoiError> {
+    let total = pixel_count(img.width, img.height)?;
+    if img.pixels.len() != total * 4 {
+        return Err(QoiError::LengthMismatch { expected: total * 4, got: img.pixels.len(), offset: None });
+    }
+    if tile_rows == 0 {
+        return Err(QoiError::InvalidTiles);
+    }
+
+    let options = EncodeOptions { channels: Some(img.channels), colorspace: img.colorspace, ..Default::default() };
+    let band_len = tile_rows as usize * img.width as usize * 4;
+    let pool = ThreadPoolBuilder::new().num_threads(threads).build().map_err(io::Error::other)?;
+    let tiles = pool.install(|| {
+        img.pixels
+            .par_chunks(band_len)
+            .map(|band| {
+                let rows = (band.len() / (img.width as usize * 4)) as u32;
+                encode_with_options(img.width, rows, 4, band, options)
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    let size = HEADER_LEN + tiles.len() * 8 + tiles.iter().map(Vec::len).sum::<usize>();
+    let mut bytes = Vec::with_capacity(size);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&img.width.to_be_bytes());
+    bytes.extend_from_slice(&img.height.to_be_bytes());
+    bytes.push(img.channels);
+    bytes.push(img.colorspace as u8);
+    bytes.extend_from_slice(&tile_rows.to_be_bytes());
+    bytes.extend_from_slice(&(tiles.len() as u32).to_be_bytes());
+    for tile in &tiles {
+        bytes.extend_from_slice(&(tile.len() as u64).to_be_bytes());
+    }
+    for tile in &tiles {
+        bytes.extend_from_slice(tile);
+    }
+    Ok(bytes)
+}
+
+/// Decodes a container written by `encode_tiled`, its bands in parallel on the global
+/// rayon pool. Input that doesn't start with `qoit` is `BadMagic`, and a container
+/// whose bands don't add up to the image it describes is `InvalidTiles`, besides the
+/// usual errors for the bands themselves. As for other decodes, images above
+/// `DEFAULT_MAX_PIXELS` are refused before anything is allocated.
+pub fn decode_tiled(bytes: &[u8]) -> Result<DecodedImage, QoiError> {
+    if bytes.len() < HEADER_LEN {
+        return Err(QoiError::TooSmall);
+    }
+    if &bytes[..4] != MAGIC {
+        return Err(QoiError::BadMagic);
+    }
+    let u32_at = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+    // Same fields and checks as a QOI header from the width on
+    let mut header = [0u8; 14];
+    header[..4].copy_from_slice(b"qoif");
+    header[4..].copy_from_slice(&bytes[4..14]);
+    let header = QoiHeader::parse(&header)?;
+    let total = pixel_count(header.width, header.height)?;
+    if total > DEFAULT_MAX_PIXELS {
+        return Err(QoiError::ImageTooLarge { width: header.width, height: header.height });
+    }
+    let (tile_rows, count) = (u32_at(14), u32_at(18) as usize);
+    if tile_rows == 0 || count != header.height.div_ceil(tile_rows) as usize {
+        return Err(QoiError::InvalidTiles);
+    }
+
+    // The table of tile lengths, then the tiles it points to, all within `bytes`
+    let lengths = bytes.get(HEADER_LEN..HEADER_LEN + count * 8).ok_or(QoiError::InvalidTiles)?;
+    let mut tiles = Vec::with_capacity(count);
+    let mut start = HEADER_LEN + count * 8;
+    for length in lengths.chunks_exact(8) {
+        let length = u64::from_be_bytes(length.try_into().unwrap());
+        let end = usize::try_from(length)
+            .ok()
+            .and_then(|length| start.checked_add(length))
+            .filter(|&end| end <= bytes.len())
+            .ok_or(QoiError::InvalidTiles)?;
+        tiles.push(&bytes[start..end]);
+        start = end;
+    }
+
+    let bands = tiles
+        .par_iter()
+        .enumerate()
+        .map(|(n, tile)| {
+            // Checked before decoding, so a band can't claim more than its share
+            let band = QoiHeader::parse(tile)?;
+            let rows = tile_rows.min(header.height - n as u32 * tile_rows);
+            if band.width != header.width || band.height != rows {
+                return Err(QoiError::InvalidTiles);
+            }
+            decode(tile)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut pixels = Vec::with_capacity(total * 4);
+    for band in bands {
+        pixels.extend_from_slice(&band.pixels);
+    }
+    Ok(DecodedImage {
+        width: header.width,
+        height: header.height,
+        channels: header.channels,
+        colorspace: header.colorspace,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Colorspace;
+
+    #[test]
+    fn tiled_round_trip() {
+        // 10 rows in bands of 4, so the last band is shorter
+        let pixels: Vec<u8> = (0..30 * 10 * 4).map(|n| (n % 7 * 40) as u8).collect();
+        let img = DecodedImage { width: 30, height: 10, channels: 4, colorspace: Colorspace::Linear, pixels };
+        let bytes = encode_tiled(&img, 4, 2).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(u32::from_be_bytes(bytes[18..22].try_into().unwrap()), 3);
+        assert_eq!(decode_tiled(&bytes).unwrap(), img);
+
+        assert!(matches!(encode_tiled(&img, 0, 1), Err(QoiError::InvalidTiles)));
+        assert!(matches!(decode_tiled(&bytes[..bytes.len() - 1]), Err(QoiError::InvalidTiles)));
+    }
+}
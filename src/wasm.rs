@@ -0,0 +1,47 @@
+// Bindings for decoding in the browser with wasm-bindgen. Build without the
+// filesystem code and run the result through wasm-bindgen:
+//
+//   cargo rustc --lib --release --target wasm32-unknown-unknown \
+//       --no-default-features --features wasm --crate-type cdylib
+//   wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/qoi_rust.wasm
+//
+// and then, from JavaScript:
+//
+//   import init, { decode_to_rgba } from "./pkg/qoi_rust.js";
+//   await init();
+//   const image = decode_to_rgba(new Uint8Array(await response.arrayBuffer()));
+//   const data = new ImageData(new Uint8ClampedArray(image.pixels), image.width, image.height);
+//   canvas.getContext("2d").putImageData(data, 0, 0);
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use wasm_bindgen::prelude::*;
+
+/// A decoded image as handed to JavaScript: flat RGBA pixels, ready for `ImageData`.
+#[wasm_bindgen]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl RgbaImage {
+    /// The RGBA pixels, 4 bytes per pixel, row by row. Copied into a new `Uint8Array`.
+    #[wasm_bindgen(getter)]
+    pub fn pixels(&self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+/// Decodes a complete QOI file. Errors are thrown as their message.
+#[wasm_bindgen]
+pub fn decode_to_rgba(bytes: &[u8]) -> Result<RgbaImage, JsValue> {
+    let image = crate::decode(bytes).map_err(|err| JsValue::from_str(&err.to_string()))?;
+    Ok(RgbaImage {
+        width: image.width,
+        height: image.height,
+        pixels: image.pixels,
+    })
+}
@@ -0,0 +1,175 @@
+#![cfg(feature = "cli")]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn qoi_rust() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_qoi-rust"))
+}
+
+// An empty directory of its own for each test, so they can run in parallel
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(any(feature = "image", feature = "png-only"))]
+#[test]
+fn converts_stdin_to_png_on_stdout() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = qoi_rust()
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(include_bytes!("../qoi-logo.qoi")).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[test]
+fn converts_a_directory_tree() {
+    let dir = temp_dir("convert-dir");
+    let (input, output) = (dir.join("in"), dir.join("out"));
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::copy("qoi-logo.qoi", input.join("logo.qoi")).unwrap();
+    fs::copy("2colors.qoi", input.join("sub/2colors.qoi")).unwrap();
+    fs::write(input.join("notes.txt"), "not an image").unwrap();
+
+    let result = qoi_rust().arg("--raw").arg("--convert-dir").arg(&input).arg(&output).output().unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.contains("Converted 2 of 2 QOI files, 0 failed, 1 other files skipped"), "{}", stdout);
+    assert_eq!(fs::metadata(output.join("logo.raw")).unwrap().len(), 719 * 325 * 4);
+    assert!(output.join("sub/2colors.raw").exists());
+    assert!(!output.join("notes.raw").exists());
+}
+
+#[test]
+fn verify_fails_on_corrupt_files() {
+    let good = qoi_rust().args(["verify", "qoi-logo.qoi"]).output().unwrap();
+    assert!(good.status.success(), "{}", String::from_utf8_lossy(&good.stderr));
+    assert_eq!(String::from_utf8_lossy(&good.stdout), "qoi-logo.qoi: OK\n");
+
+    let dir = temp_dir("verify");
+    let logo = fs::read("qoi-logo.qoi").unwrap();
+    let truncated = dir.join("truncated.qoi");
+    fs::write(&truncated, &logo[..logo.len() / 2]).unwrap();
+    let bad = qoi_rust().arg("verify").arg("qoi-logo.qoi").arg(&truncated).output().unwrap();
+    assert_eq!(bad.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&bad.stderr).contains("1 of 2 files failed verification"));
+}
+
+#[test]
+fn info_prints_json() {
+    let output = qoi_rust().args(["info", "--json", "qoi-logo.qoi"]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json = String::from_utf8_lossy(&output.stdout);
+    let size = fs::metadata("qoi-logo.qoi").unwrap().len();
+    assert_eq!(
+        json.trim_end(),
+        format!("{{\"width\":719,\"height\":325,\"channels\":4,\"colorspace\":\"srgb\",\"file_size\":{}}}", size)
+    );
+}
+
+#[test]
+fn existing_outputs_need_force() {
+    let output = temp_dir("force").join("logo.raw");
+    let convert = |force: bool| {
+        let mut command = qoi_rust();
+        command.args(["--raw", "qoi-logo.qoi"]).arg(&output);
+        if force {
+            command.arg("--force");
+        }
+        command.output().unwrap()
+    };
+
+    assert!(convert(false).status.success());
+    let again = convert(false);
+    assert_eq!(again.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&again.stderr).contains("pass --force to overwrite it"));
+    fs::write(&output, "stale").unwrap();
+    assert!(convert(true).status.success());
+    assert_eq!(fs::metadata(&output).unwrap().len(), 719 * 325 * 4);
+}
+
+#[test]
+fn frames_splits_concatenated_images() {
+    let dir = temp_dir("frames");
+    let animation = dir.join("animation.qoi");
+    let frames = [fs::read("qoi-logo.qoi").unwrap(), fs::read("2colors.qoi").unwrap()].concat();
+    fs::write(&animation, frames).unwrap();
+
+    let output = qoi_rust().args(["--raw", "frames"]).arg(&animation).arg(dir.join("out")).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("into 2 frames"));
+    assert_eq!(fs::metadata(dir.join("out/0000.raw")).unwrap().len(), 719 * 325 * 4);
+    assert!(dir.join("out/0001.raw").exists());
+    assert!(!dir.join("out/0002.raw").exists());
+}
+
+#[test]
+fn exit_codes_tell_usage_errors_from_failures() {
+    let usage = qoi_rust().output().unwrap();
+    assert_eq!(usage.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&usage.stderr).starts_with("Usage:"));
+    assert_eq!(qoi_rust().args(["--channels", "5", "qoi-logo.qoi"]).output().unwrap().status.code(), Some(2));
+
+    let dir = temp_dir("exit-codes");
+    let bad = dir.join("bad.qoi");
+    fs::write(&bad, "not a qoi file").unwrap();
+    let failed = qoi_rust().arg(&bad).arg(dir.join("bad.raw")).arg("--raw").output().unwrap();
+    assert_eq!(failed.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("Invalid magic number"));
+}
+
+#[test]
+fn debug_lists_the_ops() {
+    let output = qoi_rust().args(["--debug", "2colors.qoi"]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "2colors.qoi: 719x325, 4 channels, Srgb");
+    assert_eq!(lines[1], "      14  c0             QOI_OP_RUN   x1  #000000ff");
+    assert_eq!(lines[2], "      15  fe 33 46 5b    QOI_OP_RGB   x1  #33465bff");
+    assert_eq!(lines[3], "      19  fd             QOI_OP_RUN   x62 #33465bff");
+    assert_eq!(lines.last(), Some(&"End marker reached, 233675 pixels"));
+}
+
+#[test]
+fn info_ratio_uses_the_raw_size() {
+    let output = qoi_rust().args(["info", "--json", "--ratio", "qoi-logo.qoi"]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json = String::from_utf8_lossy(&output.stdout);
+    let raw_size = 719 * 325 * 4;
+    assert!(json.contains(&format!(",\"raw_size\":{},", raw_size)), "{}", json);
+    let ratio = raw_size as f64 / fs::metadata("qoi-logo.qoi").unwrap().len() as f64;
+    assert!(json.trim_end().ends_with(&format!("\"ratio\":{:.2}}}", ratio)), "{}", json);
+}
+
+#[test]
+fn alpha_reports_savings_for_needless_rgba_ops() {
+    // 16 opaque pixels, each written as a 5-byte QOI_OP_RGBA where QOI_OP_RGB would do
+    let mut bytes = b"qoif\0\0\0\x04\0\0\0\x04\x04\0".to_vec();
+    for n in 0..16u8 {
+        bytes.extend_from_slice(&[0xFF, n * 16, 255 - n * 16, n * 7, 255]);
+    }
+    bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    let path = temp_dir("alpha").join("opaque.qoi");
+    fs::write(&path, &bytes).unwrap();
+
+    let output = qoi_rust().arg("alpha").arg(&path).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let report = String::from_utf8_lossy(&output.stdout);
+    let saved: usize = report.trim_end().rsplit("saves ").next().unwrap().trim_end_matches(" bytes").parse().unwrap();
+    assert!(report.contains("fully opaque"), "{}", report);
+    assert!(saved > 0, "{}", report);
+}
@@ -0,0 +1,59 @@
+use proptest::prelude::*;
+
+use qoi_rust::{decode, encode_pixels};
+
+// Pixels from a handful of colors, each channel nudged by up to 40, with the odd
+// fully random one. Random bytes alone almost only give QOI_OP_RGB(A), while these
+// hit index lookups, QOI_OP_DIFF and QOI_OP_LUMA as well, including their wraparound
+// at 0 and 255. Everything shrinks towards the first color, unchanged
+fn pixel() -> impl Strategy<Value = [u8; 4]> {
+    let palette: [[u8; 4]; 4] = [[0, 0, 0, 255], [255, 255, 255, 255], [0, 0, 0, 0], [200, 30, 90, 128]];
+    prop_oneof![
+        4 => (0..palette.len(), prop::array::uniform4(-40i8..=40)).prop_map(move |(color, nudge)| {
+            let mut pixel = palette[color];
+            for (channel, nudge) in pixel.iter_mut().zip(nudge) {
+                *channel = channel.wrapping_add_signed(nudge);
+            }
+            pixel
+        }),
+        1 => prop::array::uniform4(any::<u8>()),
+    ]
+}
+
+// Width, height, channel count and pixel bytes of an image up to 64x64, made of runs
+// of repeated pixels so the encoder's runs come in all lengths. The runs are repeated
+// to fill the last row, and the width is no more than they cover. Nothing depends on
+// an earlier choice, so every part shrinks on its own and failures come down to a
+// few pixels
+fn image() -> impl Strategy<Value = (u32, u32, u8, Vec<u8>)> {
+    let runs = prop::collection::vec((pixel(), 1..=80usize), 1..=256);
+    (1..=64u32, prop_oneof![Just(3u8), Just(4u8)], runs).prop_map(|(width, channels, runs)| {
+        let run_pixels: usize = runs.iter().map(|&(_, length)| length).sum();
+        let width = width.min(run_pixels as u32);
+        let height = run_pixels.div_ceil(width as usize).min(64) as u32;
+        let pixels: Vec<u8> = runs
+            .iter()
+            .flat_map(|&(pixel, length)| core::iter::repeat_n(pixel, length))
+            .cycle()
+            .take((width * height) as usize)
+            .flat_map(|pixel| pixel[..channels as usize].to_vec())
+            .collect();
+        (width, height, channels, pixels)
+    })
+}
+
+proptest! {
+    #[test]
+    fn decode_undoes_encode((width, height, channels, pixels) in image()) {
+        let encoded = encode_pixels(width, height, channels, &pixels).unwrap();
+        let decoded = decode(&encoded).unwrap();
+
+        // The decoder always hands out RGBA, with opaque alpha for RGB input
+        let expected: Vec<u8> = match channels {
+            3 => pixels.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect(),
+            _ => pixels,
+        };
+        prop_assert_eq!((decoded.width, decoded.height), (width, height));
+        prop_assert_eq!(decoded.pixels, expected);
+    }
+}